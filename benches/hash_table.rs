@@ -0,0 +1,127 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use shared_serve::HashTable;
+use std::hint::black_box;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BUCKET_COUNTS: [usize; 3] = [16, 256, 4096];
+const KEY_COUNTS: [usize; 2] = [1_000, 10_000];
+const THREAD_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+fn keys(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("key{}", i)).collect()
+}
+
+fn filled(buckets: usize, keys: &[String]) -> HashTable {
+    let table = HashTable::new(buckets);
+    for key in keys {
+        table.insert(key, "value");
+    }
+    table
+}
+
+/// Insert, get and delete of every key from a single thread.
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash_table");
+    for key_count in KEY_COUNTS {
+        let keys = keys(key_count);
+        group.throughput(Throughput::Elements(key_count as u64));
+        for buckets in BUCKET_COUNTS {
+            let parameter = format!("{}_keys/{}_buckets", key_count, buckets);
+            group.bench_with_input(BenchmarkId::new("insert", &parameter), &buckets, |b, &buckets| {
+                b.iter_batched(
+                    || HashTable::new(buckets),
+                    |table| {
+                        for key in &keys {
+                            table.insert(key, "value");
+                        }
+                        table
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+            let table = filled(buckets, &keys);
+            group.bench_with_input(BenchmarkId::new("get", &parameter), &table, |b, table| {
+                b.iter(|| {
+                    for key in &keys {
+                        black_box(table.get(key));
+                    }
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("delete", &parameter), &buckets, |b, &buckets| {
+                b.iter_batched(
+                    || filled(buckets, &keys),
+                    |table| {
+                        for key in &keys {
+                            black_box(table.delete(key));
+                        }
+                        table
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Threads working on one table, each on its own keys, with 9 gets per insert.
+fn contended(c: &mut Criterion) {
+    const OPERATIONS_PER_THREAD: usize = 10_000;
+    let mut group = c.benchmark_group("hash_table_contended");
+    group.sample_size(20);
+    for buckets in [16, 4096] {
+        for threads in THREAD_COUNTS {
+            group.throughput(Throughput::Elements((threads * OPERATIONS_PER_THREAD) as u64));
+            let parameter = format!("{}_buckets/{}_threads", buckets, threads);
+            group.bench_function(BenchmarkId::from_parameter(parameter), |b| {
+                let key_sets: Vec<Vec<String>> = (0..threads)
+                    .map(|thread| (0..1_000).map(|i| format!("key{}_{}", thread, i)).collect())
+                    .collect();
+                let table = Arc::new(filled(buckets, &key_sets.concat()));
+                b.iter_custom(|iterations| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iterations {
+                        elapsed += run_threads(&table, &key_sets, OPERATIONS_PER_THREAD);
+                    }
+                    elapsed
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Time until every thread did its operations, once all of them started.
+fn run_threads(table: &Arc<HashTable>, key_sets: &[Vec<String>], operations: usize) -> Duration {
+    let barrier = Arc::new(Barrier::new(key_sets.len() + 1));
+    let handles: Vec<_> = key_sets
+        .iter()
+        .cloned()
+        .map(|keys| {
+            let table = table.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..operations {
+                    let key = &keys[i % keys.len()];
+                    if i % 10 == 0 {
+                        table.insert(key, "value");
+                    } else {
+                        black_box(table.get(key));
+                    }
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let started = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    started.elapsed()
+}
+
+criterion_group!(benches, single_thread, contended);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use shared_serve::{Dequeued, Operation, Request, Reservation, Segment};
+use std::hint::black_box;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PRODUCER_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+/// A segment of its own, removed again when the benchmark is done.
+struct BenchSegment {
+    segment: Arc<Segment>,
+    name: String,
+}
+
+impl BenchSegment {
+    fn create(benchmark: &str) -> Self {
+        let name = format!("BenchQueue_{}_{}", benchmark, std::process::id());
+        let (segment, _) = Segment::create(&name, false).expect("Failed to create segment");
+        BenchSegment { segment: Arc::new(segment), name }
+    }
+}
+
+impl Drop for BenchSegment {
+    fn drop(&mut self) {
+        let _ = Segment::unlink(&self.name);
+    }
+}
+
+/// Enqueues the request the way `Client` does, yielding while the ring is locked or full.
+fn enqueue(segment: &Segment, request: &Request) {
+    loop {
+        match segment.try_reserve() {
+            Reservation::Reserved(slot) => return slot.commit(request),
+            Reservation::Locked | Reservation::Full => thread::yield_now(),
+            Reservation::Closed => panic!("Segment was closed"),
+        }
+    }
+}
+
+fn dequeue(segment: &Segment) -> Request {
+    loop {
+        if let Dequeued::Request(request) = segment.try_dequeue() {
+            return request;
+        }
+        thread::yield_now();
+    }
+}
+
+/// One request through the ring and back out, without contention.
+fn round_trip(c: &mut Criterion) {
+    let bench = BenchSegment::create("round_trip");
+    let request = Request::new(Operation::INSERT, "key", "value");
+    let mut group = c.benchmark_group("queue");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("enqueue_dequeue", |b| {
+        b.iter(|| {
+            enqueue(&bench.segment, black_box(&request));
+            black_box(dequeue(&bench.segment))
+        })
+    });
+    group.finish();
+}
+
+/// Producer threads filling the ring while the benchmark thread drains it,
+/// like clients and the server's dispatch loop.
+fn producers(c: &mut Criterion) {
+    const REQUESTS_PER_PRODUCER: usize = 1_000;
+    let bench = BenchSegment::create("producers");
+    let mut group = c.benchmark_group("queue_producers");
+    group.sample_size(20);
+    for producers in PRODUCER_COUNTS {
+        group.throughput(Throughput::Elements((producers * REQUESTS_PER_PRODUCER) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(producers), &producers, |b, &producers| {
+            b.iter_custom(|iterations| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iterations {
+                    elapsed += run_producers(&bench.segment, producers, REQUESTS_PER_PRODUCER);
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+fn run_producers(segment: &Arc<Segment>, producers: usize, requests: usize) -> Duration {
+    let barrier = Arc::new(Barrier::new(producers + 1));
+    let handles: Vec<_> = (0..producers)
+        .map(|producer| {
+            let segment = segment.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let request = Request::new(Operation::INSERT, &format!("key{}", producer), "value");
+                barrier.wait();
+                for _ in 0..requests {
+                    enqueue(&segment, &request);
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let started = Instant::now();
+    for _ in 0..producers * requests {
+        black_box(dequeue(segment));
+    }
+    let elapsed = started.elapsed();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    elapsed
+}
+
+criterion_group!(benches, round_trip, producers);
+criterion_main!(benches);
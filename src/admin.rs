@@ -0,0 +1,340 @@
+use crate::segment::{process_alive, SEGMENT_MAGIC, SEGMENT_VERSION, STATE_PRESERVED, STATE_SERVING, STATE_SHUTTING_DOWN};
+use crate::{Request, Segment, NO_REPLY};
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// Where Linux keeps the POSIX shared memory objects.
+pub const SHM_DIR: &str = "/dev/shm";
+
+/// What `shmadmin` shows of a segment.
+///
+/// The snapshot is taken field by field while the server and its clients keep
+/// going, so on a busy segment the indices and slots may not quite agree.
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentDump {
+    pub name: String,
+    /// The magic number, as hex.
+    pub magic: String,
+    pub version: u32,
+    /// Whether magic and version match this build. The rest of the segment is
+    /// only read if they do.
+    pub valid: bool,
+    pub header: Option<HeaderDump>,
+    pub clients: Vec<ClientDump>,
+    pub slots: Vec<SlotDump>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeaderDump {
+    pub capacity: usize,
+    pub state: &'static str,
+    pub server_pid: u32,
+    pub server_alive: bool,
+    pub generation: u64,
+    pub read_index: usize,
+    pub write_index: usize,
+    pub pending: usize,
+    /// Pid of the client holding the write lock, 0 if unlocked.
+    pub write_lock: u32,
+    pub write_lock_alive: bool,
+    pub queue_full: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientDump {
+    pub pid: u32,
+    pub slot: u32,
+    pub alive: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotDump {
+    pub index: usize,
+    /// Whether the slot lies between the read and the write index.
+    pub pending: bool,
+    /// `empty`, `reserved` or `committed`.
+    pub state: &'static str,
+    /// Pid of the client that reserved the slot last.
+    pub owner: u32,
+    pub owner_alive: bool,
+    /// The request of a committed slot.
+    pub request: Option<RequestDump>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestDump {
+    pub client: String,
+    pub request_id: u64,
+    pub operation: String,
+    pub key: String,
+    pub value: String,
+    /// Index of the reply slot the client waits on, none for fire-and-forget requests.
+    pub reply_slot: Option<u32>,
+    pub timestamp_us: u64,
+    /// The request as the server logs it.
+    #[serde(skip)]
+    pub display: String,
+}
+
+impl RequestDump {
+    fn new(request: &Request) -> Self {
+        RequestDump {
+            client: request.client.to_string(),
+            request_id: request.request_id,
+            operation: format!("{:?}", request.operation),
+            key: request.key_str().to_string(),
+            value: request.value_str().to_string(),
+            reply_slot: (request.reply_slot != NO_REPLY).then_some(request.reply_slot),
+            timestamp_us: request.timestamp_us,
+            display: request.to_string(),
+        }
+    }
+}
+
+fn state_name(state: u8) -> &'static str {
+    match state {
+        STATE_SERVING => "serving",
+        STATE_PRESERVED => "preserved",
+        STATE_SHUTTING_DOWN => "shutting down",
+        _ => "unknown",
+    }
+}
+
+impl SegmentDump {
+    /// Reads the segment, which may be mapped read-only. Only the pending
+    /// slots are included unless `all_slots` is set.
+    pub fn capture(name: &str, segment: &Segment, all_slots: bool) -> Self {
+        let header = segment.header();
+        // The segment only knows the size of its ring if the header is of this layout
+        let valid = segment.capacity() > 0;
+        let mut dump = SegmentDump {
+            name: name.to_string(),
+            magic: format!("{:#018x}", header.magic),
+            version: header.version,
+            valid,
+            header: None,
+            clients: Vec::new(),
+            slots: Vec::new(),
+        };
+        // Past the magic and version, the layout may be anything
+        if !valid {
+            return dump;
+        }
+
+        let server_pid = header.server_pid.load(Ordering::Acquire);
+        let write_lock = header.write_lock.load(Ordering::Acquire);
+        let read_index = header.read_index.load(Ordering::Acquire);
+        let pending = header.pending();
+        dump.header = Some(HeaderDump {
+            capacity: segment.capacity(),
+            state: state_name(header.state.load(Ordering::Acquire)),
+            server_pid,
+            server_alive: process_alive(server_pid),
+            generation: header.generation.load(Ordering::Acquire),
+            read_index,
+            write_index: header.write_index.load(Ordering::Acquire),
+            pending,
+            write_lock,
+            write_lock_alive: process_alive(write_lock),
+            queue_full: header.queue_full.load(Ordering::Relaxed),
+        });
+        dump.clients = segment
+            .registered_clients()
+            .into_iter()
+            .map(|id| ClientDump { pid: id.pid, slot: id.slot, alive: process_alive(id.pid) })
+            .collect();
+
+        // Starting at the read index lists the pending requests in the order the server takes them
+        let capacity = segment.capacity();
+        for offset in 0..capacity {
+            let index = (read_index + offset) % capacity;
+            let is_pending = offset < pending;
+            if !is_pending && !all_slots {
+                break;
+            }
+            let slot = segment.request_slot(index);
+            let owner = slot.owner();
+            let (state, request) = if slot.is_committed() {
+                ("committed", Some(RequestDump::new(&slot.read())))
+            } else if slot.is_reserved() {
+                // The client may be halfway through copying its request in
+                ("reserved", None)
+            } else {
+                ("empty", None)
+            };
+            dump.slots.push(SlotDump { index, pending: is_pending, state, owner, owner_alive: process_alive(owner), request });
+        }
+        dump
+    }
+}
+
+fn liveness(alive: bool) -> &'static str {
+    if alive {
+        "alive"
+    } else {
+        "dead"
+    }
+}
+
+impl fmt::Display for SegmentDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Segment: {}", self.name)?;
+        writeln!(f, "Magic: {}", self.magic)?;
+        writeln!(f, "Version: {} (this build uses {})", self.version, SEGMENT_VERSION)?;
+        let Some(header) = &self.header else {
+            return write!(f, "Not a segment this build can read");
+        };
+
+        writeln!(f, "State: {}, server pid {} ({})", header.state, header.server_pid, liveness(header.server_alive))?;
+        writeln!(f, "Generation: {}", header.generation)?;
+        writeln!(
+            f,
+            "Capacity: {}, Read index: {}, Write index: {}, Pending: {}",
+            header.capacity, header.read_index, header.write_index, header.pending
+        )?;
+        if header.write_lock == 0 {
+            writeln!(f, "Write lock: unlocked")?;
+        } else {
+            writeln!(f, "Write lock: held by pid {} ({})", header.write_lock, liveness(header.write_lock_alive))?;
+        }
+        writeln!(f, "Queue full rejections: {}", header.queue_full)?;
+        let clients: Vec<String> = self
+            .clients
+            .iter()
+            .map(|client| format!("{}:{} ({})", client.pid, client.slot, liveness(client.alive)))
+            .collect();
+        if clients.is_empty() {
+            writeln!(f, "Clients: none")?;
+        } else {
+            writeln!(f, "Clients: {}", clients.join(", "))?;
+        }
+
+        write!(f, "Slots:")?;
+        if self.slots.is_empty() {
+            write!(f, " none pending")?;
+        }
+        for slot in &self.slots {
+            let marker = if slot.pending { "*" } else { " " };
+            write!(f, "\n {}[{}] ", marker, slot.index)?;
+            match &slot.request {
+                Some(request) => write!(f, "{:<9} {}", slot.state, request.display)?,
+                None if slot.state == "reserved" => {
+                    write!(f, "{:<9} by pid {} ({})", slot.state, slot.owner, liveness(slot.owner_alive))?
+                }
+                None => write!(f, "{}", slot.state)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a segment is still needed, as far as `shmadmin cleanup` can tell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SegmentStatus {
+    /// The server that owns it is running.
+    Live,
+    /// The last server left it for the next one with `--preserve-queue`.
+    Preserved,
+    /// Its server exited without removing it, usually because it crashed.
+    Orphaned,
+    /// Written by a build with another layout, so its owner can't be checked.
+    UnknownLayout,
+}
+
+impl fmt::Display for SegmentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentStatus::Live => write!(f, "live"),
+            SegmentStatus::Preserved => write!(f, "preserved"),
+            SegmentStatus::Orphaned => write!(f, "orphaned"),
+            SegmentStatus::UnknownLayout => write!(f, "unknown layout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub status: SegmentStatus,
+    pub version: u32,
+    /// Pid of the server that owns or last owned the segment, 0 if unknown.
+    pub server_pid: u32,
+    pub server_alive: bool,
+    pub pending: usize,
+}
+
+impl SegmentInfo {
+    /// Looks at the shared memory object `name`. Returns `None` if it
+    /// doesn't exist, can't be read or wasn't created by a shared_serve server.
+    pub fn probe(name: &str) -> Option<Self> {
+        // Every layout starts with the magic and the version
+        let mut start = [0u8; 12];
+        File::open(Path::new(SHM_DIR).join(name)).ok()?.read_exact(&mut start).ok()?;
+        if u64::from_ne_bytes(start[..8].try_into().unwrap()) != SEGMENT_MAGIC {
+            return None;
+        }
+        let version = u32::from_ne_bytes(start[8..].try_into().unwrap());
+
+        let segment = match Segment::open_read_only(name) {
+            Ok(segment) if segment.capacity() > 0 => segment,
+            _ => {
+                return Some(SegmentInfo {
+                    name: name.to_string(),
+                    status: SegmentStatus::UnknownLayout,
+                    version,
+                    server_pid: 0,
+                    server_alive: false,
+                    pending: 0,
+                })
+            }
+        };
+        let header = segment.header();
+        let server_pid = header.server_pid.load(Ordering::Acquire);
+        let server_alive = process_alive(server_pid);
+        let status = if header.state.load(Ordering::Acquire) == STATE_PRESERVED {
+            SegmentStatus::Preserved
+        } else if server_alive {
+            SegmentStatus::Live
+        } else {
+            SegmentStatus::Orphaned
+        };
+        Some(SegmentInfo { name: name.to_string(), status, version, server_pid, server_alive, pending: header.pending() })
+    }
+
+    /// Every shared_serve segment in `SHM_DIR`, sorted by name.
+    pub fn find_all() -> io::Result<Vec<Self>> {
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(SHM_DIR)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(info) = entry.file_name().to_str().and_then(Self::probe) {
+                segments.push(info);
+            }
+        }
+        segments.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(segments)
+    }
+}
+
+impl fmt::Display for SegmentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.status)?;
+        match self.status {
+            SegmentStatus::UnknownLayout => write!(f, ", version {}", self.version),
+            _ => write!(
+                f,
+                ", server pid {} ({}), {} pending",
+                self.server_pid,
+                liveness(self.server_alive),
+                self.pending
+            ),
+        }
+    }
+}
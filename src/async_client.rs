@@ -0,0 +1,302 @@
+use crate::connection::{try_claim_reply_slot, try_enqueue, Attachment, Connection, POLL_INTERVAL};
+use crate::segment::{Reply, ReplyStatus};
+use crate::{ClientConfig, ClientError, ClientId, Operation, Request, Stats, STATS_PAGES};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+type CallResult = Result<Reply, ClientError>;
+
+/// Completion state shared between a `Call` future and the waiter thread.
+#[derive(Default)]
+struct Completion {
+    reply: Option<CallResult>,
+    waker: Option<Waker>,
+}
+
+type CompletionHandle = Arc<Mutex<Completion>>;
+
+fn finish(completion: &CompletionHandle, reply: CallResult) {
+    let mut completion = completion.lock().unwrap();
+    completion.reply = Some(reply);
+    if let Some(waker) = completion.waker.take() {
+        waker.wake();
+    }
+}
+
+struct Submission {
+    request: Request,
+    deadline: Instant,
+    completion: CompletionHandle,
+}
+
+struct InFlight {
+    /// The reply slot belongs to the segment the request was sent to.
+    attachment: Arc<Attachment>,
+    slot: usize,
+    deadline: Instant,
+    completion: CompletionHandle,
+}
+
+struct Shared {
+    connection: Connection,
+    config: ClientConfig,
+    next_request_id: AtomicU64,
+    submissions: Mutex<VecDeque<Submission>>,
+    shutdown: AtomicBool,
+}
+
+/// A client whose operations return futures instead of blocking.
+///
+/// A single waiter thread per client enqueues submitted requests and polls
+/// the reply slots, waking the futures once their reply arrives. The futures
+/// don't depend on a particular runtime, so they can be awaited from tokio
+/// or any other executor. Each request in flight holds one of the segment's
+/// `REPLY_SLOTS` reply slots, which all clients of the server share, so at
+/// most that many requests are enqueued or being processed at once. Further
+/// submissions are held back by the waiter, in call order, until a slot is
+/// free, and they also wait for room in the queue and for the write lock. Once they waited for `lock_timeout`, they fail with
+/// `NoReplySlot`, `QueueFull` or `LockTimeout`, depending on which of the
+/// three was missing.
+///
+/// Requests are submitted when the method is called rather than when the
+/// future is first polled, so the server applies them in call order even if
+/// the futures are awaited later or in a different order.
+///
+/// Like `Client`, it reattaches to a restarted server before enqueueing
+/// further requests.
+pub struct AsyncClient {
+    shared: Arc<Shared>,
+    waiter: Option<JoinHandle<()>>,
+}
+
+impl AsyncClient {
+    pub fn connect(name: &str) -> Result<Self, ClientError> {
+        Self::connect_with(name, ClientConfig::default())
+    }
+
+    pub fn connect_with(name: &str, config: ClientConfig) -> Result<Self, ClientError> {
+        let connection = Connection::open(name, config.reconnect.clone())?;
+        let shared = Arc::new(Shared {
+            connection,
+            config,
+            next_request_id: AtomicU64::new(1),
+            submissions: Mutex::new(VecDeque::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let waiter = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("shared_serve-waiter".to_string())
+                .spawn(move || run_waiter(&shared))
+                .expect("Failed to spawn waiter thread")
+        };
+        Ok(AsyncClient { shared, waiter: Some(waiter) })
+    }
+
+    /// The identity the server sees on requests from this client.
+    /// It changes when the client reattaches to a restarted server.
+    pub fn id(&self) -> ClientId {
+        self.shared.connection.id()
+    }
+
+    pub fn get(&self, key: &str) -> impl Future<Output = Result<Option<String>, ClientError>> {
+        let call = self.call(Request::new(Operation::GET, key, ""));
+        async move {
+            let Reply { status, value, .. } = call.await?;
+            Ok(match status {
+                ReplyStatus::Ok => Some(value),
+                ReplyStatus::NotFound | ReplyStatus::Full => None,
+            })
+        }
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> impl Future<Output = Result<(), ClientError>> {
+        let call = self.call(Request::new(Operation::INSERT, key, value));
+        async move {
+            match call.await?.status {
+                ReplyStatus::Full => Err(ClientError::TableFull),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Resolves to whether the key was present.
+    pub fn delete(&self, key: &str) -> impl Future<Output = Result<bool, ClientError>> {
+        let call = self.call(Request::new(Operation::DELETE, key, ""));
+        async move { Ok(call.await?.status == ReplyStatus::Ok) }
+    }
+
+    /// Resolves to the server's counters, requesting all of their pages at once.
+    pub fn stats(&self) -> impl Future<Output = Result<Stats, ClientError>> {
+        let calls: Vec<Call> = (0..STATS_PAGES).map(|page| self.call(Request::new(Operation::STATS, &page.to_string(), ""))).collect();
+        async move {
+            let mut pages = Vec::with_capacity(STATS_PAGES);
+            for call in calls {
+                let Reply { status, value, .. } = call.await?;
+                if status != ReplyStatus::Ok {
+                    return Err(ClientError::InvalidReply);
+                }
+                pages.push(value);
+            }
+            Stats::parse(&pages.join(" ")).ok_or(ClientError::InvalidReply)
+        }
+    }
+
+    fn call(&self, mut request: Request) -> Call {
+        let completion = CompletionHandle::default();
+        // Stamped with the client id once the waiter knows which segment it goes to
+        request.request_id = self.shared.next_request_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.submissions.lock().unwrap().push_back(Submission {
+            request,
+            deadline: Instant::now() + self.shared.config.lock_timeout,
+            completion: completion.clone(),
+        });
+        if let Some(waiter) = &self.waiter {
+            waiter.thread().unpark();
+        }
+        Call { completion }
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        if let Some(waiter) = self.waiter.take() {
+            waiter.thread().unpark();
+            let _ = waiter.join();
+        }
+    }
+}
+
+/// Future resolved by the waiter thread once the server has replied.
+struct Call {
+    completion: CompletionHandle,
+}
+
+impl Future for Call {
+    type Output = CallResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<CallResult> {
+        let mut completion = self.completion.lock().unwrap();
+        match completion.reply.take() {
+            Some(reply) => Poll::Ready(reply),
+            None => {
+                completion.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn run_waiter(shared: &Shared) {
+    let mut in_flight: Vec<InFlight> = Vec::new();
+    let mut waiting: VecDeque<Submission> = VecDeque::new();
+    // Why the submission at the front of `waiting` couldn't be enqueued yet
+    let mut blocked_on = ClientError::QueueFull;
+
+    while !shared.shutdown.load(Ordering::Acquire) {
+        waiting.extend(shared.submissions.lock().unwrap().drain(..));
+        let now = Instant::now();
+
+        let attachment = if waiting.is_empty() {
+            None
+        } else {
+            match shared.connection.current() {
+                Ok(attachment) => Some(attachment),
+                Err(e) => {
+                    for submission in waiting.drain(..) {
+                        finish(&submission.completion, Err(e.clone()));
+                    }
+                    None
+                }
+            }
+        };
+
+        // Hand submissions to the server in the order they were made
+        while let (Some(submission), Some(attachment)) = (waiting.front(), &attachment) {
+            let segment = &attachment.segment;
+            let Some(slot) = try_claim_reply_slot(segment) else {
+                blocked_on = ClientError::NoReplySlot;
+                break;
+            };
+            let mut request = submission.request;
+            request.reply_slot = slot as u32;
+            request.stamp(attachment.id, request.request_id);
+            match try_enqueue(segment, &request) {
+                Ok(true) => {
+                    let submission = waiting.pop_front().unwrap();
+                    in_flight.push(InFlight {
+                        attachment: attachment.clone(),
+                        slot,
+                        deadline: now + shared.config.reply_timeout,
+                        completion: submission.completion,
+                    });
+                    continue;
+                }
+                // Another client holds the write lock
+                Ok(false) => {
+                    segment.reply_slot(slot).unwrap().release();
+                    blocked_on = ClientError::LockTimeout;
+                }
+                Err(ClientError::QueueFull) => {
+                    segment.reply_slot(slot).unwrap().release();
+                    blocked_on = ClientError::QueueFull;
+                }
+                // Reattach on the next round
+                Err(ClientError::ShuttingDown) if shared.connection.policy().is_enabled() => {
+                    segment.reply_slot(slot).unwrap().release();
+                }
+                Err(e) => {
+                    segment.reply_slot(slot).unwrap().release();
+                    let submission = waiting.pop_front().unwrap();
+                    finish(&submission.completion, Err(e));
+                    continue;
+                }
+            }
+            break;
+        }
+
+        // Fail submissions that couldn't be enqueued in time
+        while waiting.front().is_some_and(|submission| submission.deadline <= now) {
+            let submission = waiting.pop_front().unwrap();
+            finish(&submission.completion, Err(blocked_on.clone()));
+        }
+
+        in_flight.retain(|request| {
+            let slot = request.attachment.segment.reply_slot(request.slot).unwrap();
+            if let Some(reply) = slot.take() {
+                finish(&request.completion, Ok(reply));
+                return false;
+            }
+            if request.deadline <= now && (slot.abandon() || !slot.is_answered()) {
+                finish(&request.completion, Err(ClientError::ReplyTimeout));
+                return false;
+            }
+            true
+        });
+
+        if in_flight.is_empty() && waiting.is_empty() {
+            thread::park();
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Nobody will answer the remaining futures once the client is gone
+    for request in in_flight {
+        let slot = request.attachment.segment.reply_slot(request.slot).unwrap();
+        if !slot.abandon() {
+            slot.take();
+        }
+        finish(&request.completion, Err(ClientError::ReplyTimeout));
+    }
+    for submission in waiting.into_iter().chain(shared.submissions.lock().unwrap().drain(..)) {
+        finish(&submission.completion, Err(ClientError::ReplyTimeout));
+    }
+}
@@ -0,0 +1,366 @@
+use shared_serve::workload::{key_name, Action, KeyChooser, KeyDistribution, LatencyRecorder, Mix, Preset, Rng};
+use shared_serve::{Client, ClientError, DEFAULT_SEGMENT_NAME};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Keys inserted during the run by worker process `n` start at `records + n * INSERT_STRIDE`,
+/// so processes never insert the same key.
+const INSERT_STRIDE: u64 = 1 << 40;
+
+/// Drives a workload against a running server and reports throughput and latency.
+#[derive(Parser, Clone)]
+struct Args {
+    /// Name of the shared memory segment
+    #[arg(long, default_value = DEFAULT_SEGMENT_NAME)]
+    name: String,
+    /// YCSB core workload to run, a to f
+    #[arg(short, long, default_value = "a")]
+    workload: Preset,
+    /// Proportion of reads, replacing the mix of the workload
+    #[arg(long)]
+    read: Option<f64>,
+    /// Proportion of updates of existing keys, replacing the mix of the workload
+    #[arg(long)]
+    update: Option<f64>,
+    /// Proportion of inserts of new keys, replacing the mix of the workload
+    #[arg(long)]
+    insert: Option<f64>,
+    /// Proportion of scans, replacing the mix of the workload
+    #[arg(long)]
+    scan: Option<f64>,
+    /// Proportion of read-modify-writes, replacing the mix of the workload
+    #[arg(long)]
+    rmw: Option<f64>,
+    /// How keys are picked: uniform, zipfian or latest [default: that of the workload]
+    #[arg(long)]
+    distribution: Option<KeyDistribution>,
+    /// Keys inserted before the run
+    #[arg(long, default_value = "1000")]
+    records: u64,
+    /// Operations per client thread
+    #[arg(long, default_value = "10000")]
+    operations: u64,
+    /// Run for this many seconds instead of a fixed number of operations
+    #[arg(long)]
+    duration: Option<u64>,
+    /// Client threads per process
+    #[arg(short, long, default_value = "4")]
+    threads: u64,
+    /// Client processes, each running `--threads` clients
+    #[arg(short, long, default_value = "1")]
+    processes: u64,
+    /// Length of the keys, at most 64
+    #[arg(long, default_value = "16")]
+    key_size: usize,
+    /// Length of the values, at most 256
+    #[arg(long, default_value = "100")]
+    value_size: usize,
+    /// Most keys a scan reads
+    #[arg(long, default_value = "10")]
+    scan_length: u64,
+    /// Don't insert the records before the run, e.g. when they are left from a previous run
+    #[arg(long)]
+    skip_load: bool,
+    /// Seed of the random choices, to repeat a run
+    #[arg(long, default_value = "42")]
+    seed: u64,
+    /// Runs as worker process `n` of a multi-process run, which skips the load and prints its results as JSON
+    #[arg(long, hide = true)]
+    worker: Option<u64>,
+}
+
+impl Args {
+    /// Whether the proportions were given instead of taken from the workload.
+    fn custom_mix(&self) -> bool {
+        [self.read, self.update, self.insert, self.scan, self.rmw].iter().any(Option::is_some)
+    }
+
+    fn mix(&self) -> Mix {
+        if !self.custom_mix() {
+            return self.workload.mix();
+        }
+        Mix {
+            read: self.read.unwrap_or(0.0),
+            update: self.update.unwrap_or(0.0),
+            insert: self.insert.unwrap_or(0.0),
+            scan: self.scan.unwrap_or(0.0),
+            read_modify_write: self.rmw.unwrap_or(0.0),
+        }
+    }
+
+    fn distribution(&self) -> KeyDistribution {
+        self.distribution.unwrap_or(self.workload.distribution())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.key_size < 8 || self.key_size > 64 {
+            return Err("--key-size must be between 8 and 64".to_string());
+        }
+        if self.value_size > 256 {
+            return Err("--value-size must be at most 256".to_string());
+        }
+        if self.records == 0 || self.threads == 0 || self.processes == 0 || self.scan_length == 0 {
+            return Err("--records, --threads, --processes and --scan-length must be greater than 0".to_string());
+        }
+        let mix = self.mix();
+        let proportions = [mix.read, mix.update, mix.insert, mix.scan, mix.read_modify_write];
+        if proportions.iter().any(|p| *p < 0.0 || p.is_nan()) || mix.total() <= 0.0 {
+            return Err("The proportions of the operations must not be negative and must not all be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Results of one process, merged over its threads.
+#[derive(Default, Serialize, Deserialize)]
+struct Report {
+    elapsed: Duration,
+    /// Indexed like `Action::ALL`.
+    latencies: [LatencyRecorder; 5],
+    errors: [u64; 5],
+    /// Enqueue attempts repeated because the request queue was full.
+    queue_full: u64,
+}
+
+impl Report {
+    fn merge(&mut self, other: &Report) {
+        self.elapsed = self.elapsed.max(other.elapsed);
+        for (latencies, other) in self.latencies.iter_mut().zip(&other.latencies) {
+            latencies.merge(other);
+        }
+        for (errors, other) in self.errors.iter_mut().zip(other.errors) {
+            *errors += other;
+        }
+        self.queue_full += other.queue_full;
+    }
+}
+
+/// State shared by the client threads of a process.
+struct Run {
+    args: Args,
+    mix: Mix,
+    keys: KeyChooser,
+    value: String,
+    /// Keys inserted by this process during the run.
+    inserted: AtomicU64,
+    /// Start of the keys this process inserts.
+    insert_base: u64,
+}
+
+impl Run {
+    /// The index of the key at `position` among the loaded keys followed by
+    /// the ones inserted by this process.
+    fn key(&self, position: u64) -> String {
+        let index = if position < self.args.records {
+            position
+        } else {
+            self.insert_base + position - self.args.records
+        };
+        key_name(index, self.args.key_size)
+    }
+}
+
+/// Repeats a request while the queue is full, counting the repetitions.
+fn retry<T>(queue_full: &mut u64, mut request: impl FnMut() -> Result<T, ClientError>) -> Result<T, ClientError> {
+    loop {
+        match request() {
+            Err(ClientError::QueueFull) => {
+                *queue_full += 1;
+                thread::sleep(Duration::from_micros(50));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn perform(run: &Run, client: &Client, rng: &mut Rng, action: Action, queue_full: &mut u64) -> Result<(), ClientError> {
+    let stored = run.args.records + run.inserted.load(Ordering::Relaxed);
+    let key = run.key(run.keys.next(rng, stored));
+    match action {
+        Action::Read => retry(queue_full, || client.get(&key)).map(drop),
+        Action::Update => retry(queue_full, || client.insert(&key, &run.value)),
+        Action::Insert => {
+            let position = run.args.records + run.inserted.fetch_add(1, Ordering::Relaxed);
+            retry(queue_full, || client.insert(&run.key(position), &run.value))
+        }
+        Action::Scan => {
+            let start = run.keys.next(rng, stored);
+            let length = 1 + rng.below(run.args.scan_length);
+            for position in start..(start + length).min(stored) {
+                retry(queue_full, || client.get(&run.key(position)))?;
+            }
+            Ok(())
+        }
+        Action::ReadModifyWrite => {
+            retry(queue_full, || client.get(&key))?;
+            retry(queue_full, || client.insert(&key, &run.value))
+        }
+    }
+}
+
+fn run_thread(run: &Run, thread_index: u64) -> Result<Report, ClientError> {
+    let client = Client::connect(&run.args.name)?;
+    let process = run.args.worker.unwrap_or(0);
+    let mut rng = Rng::new(run.args.seed ^ (process << 32 | thread_index));
+    let mut report = Report::default();
+    let deadline = run.args.duration.map(|secs| Instant::now() + Duration::from_secs(secs));
+
+    let started = Instant::now();
+    let mut done = 0;
+    loop {
+        let finished = match deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => done >= run.args.operations,
+        };
+        if finished {
+            break;
+        }
+        let action = run.mix.pick(&mut rng);
+        let index = Action::ALL.iter().position(|a| *a == action).unwrap();
+        let operation_started = Instant::now();
+        match perform(run, &client, &mut rng, action, &mut report.queue_full) {
+            Ok(()) => report.latencies[index].record(operation_started.elapsed()),
+            Err(_) => report.errors[index] += 1,
+        }
+        done += 1;
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Runs the client threads of this process.
+fn run_process(args: &Args) -> Result<Report, Box<dyn Error>> {
+    let process = args.worker.unwrap_or(0);
+    let run = Arc::new(Run {
+        args: args.clone(),
+        mix: args.mix(),
+        keys: KeyChooser::new(args.distribution(), args.records),
+        value: "x".repeat(args.value_size),
+        inserted: AtomicU64::new(0),
+        insert_base: args.records + process * INSERT_STRIDE,
+    });
+    let handles: Vec<_> = (0..args.threads)
+        .map(|thread_index| {
+            let run = run.clone();
+            thread::spawn(move || run_thread(&run, thread_index))
+        })
+        .collect();
+    let mut report = Report::default();
+    for handle in handles {
+        report.merge(&handle.join().map_err(|_| "Client thread panicked")??);
+    }
+    Ok(report)
+}
+
+/// Starts the worker processes and merges their reports.
+fn run_processes(args: &Args) -> Result<Report, Box<dyn Error>> {
+    let executable = std::env::current_exe()?;
+    // Skip the program name, the workers get the same options
+    let forwarded: Vec<String> = std::env::args().skip(1).collect();
+    let workers: Vec<_> = (0..args.processes)
+        .map(|process| {
+            Command::new(&executable)
+                .args(&forwarded)
+                .args(["--worker", &process.to_string()])
+                .stdout(Stdio::piped())
+                .spawn()
+        })
+        .collect::<Result<_, _>>()?;
+    let mut report = Report::default();
+    for worker in workers {
+        let output = worker.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!("Worker process failed with {}", output.status).into());
+        }
+        report.merge(&serde_json::from_slice(&output.stdout)?);
+    }
+    Ok(report)
+}
+
+/// Inserts the records the run works on.
+fn load(args: &Args) -> Result<Duration, Box<dyn Error>> {
+    let started = Instant::now();
+    let value = "x".repeat(args.value_size);
+    let handles: Vec<_> = (0..args.threads)
+        .map(|thread_index| {
+            let args = args.clone();
+            let value = value.clone();
+            thread::spawn(move || -> Result<(), ClientError> {
+                let client = Client::connect(&args.name)?;
+                let mut queue_full = 0;
+                for index in (thread_index..args.records).step_by(args.threads as usize) {
+                    retry(&mut queue_full, || client.insert(&key_name(index, args.key_size), &value))?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().map_err(|_| "Load thread panicked")??;
+    }
+    Ok(started.elapsed())
+}
+
+fn print_report(args: &Args, report: &Report) {
+    let operations: u64 = report.latencies.iter().map(LatencyRecorder::count).sum::<u64>() + report.errors.iter().sum::<u64>();
+    let seconds = report.elapsed.as_secs_f64();
+    println!(
+        "Ran {} operations in {:.2}s with {} clients: {:.0} ops/s",
+        operations,
+        seconds,
+        args.threads * args.processes,
+        operations as f64 / seconds.max(f64::EPSILON)
+    );
+    println!(
+        "{:<18} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        "Operation", "Count", "Mean (us)", "p50 (us)", "p99 (us)", "p999 (us)", "Max (us)", "Errors"
+    );
+    for ((action, latencies), errors) in Action::ALL.iter().zip(&report.latencies).zip(report.errors) {
+        if latencies.count() == 0 && errors == 0 {
+            continue;
+        }
+        println!(
+            "{:<18} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+            action.name(),
+            latencies.count(),
+            latencies.mean().as_micros(),
+            latencies.percentile(0.5).as_micros(),
+            latencies.percentile(0.99).as_micros(),
+            latencies.percentile(0.999).as_micros(),
+            latencies.max().as_micros(),
+            errors
+        );
+    }
+    println!("Queue full retries: {}", report.queue_full);
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    args.validate()?;
+
+    if args.worker.is_some() {
+        let report = run_process(&args)?;
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(());
+    }
+
+    let workload = if args.custom_mix() { "custom".to_string() } else { format!("{:?}", args.workload) };
+    println!("Workload: {} ({}), {} keys", workload, args.mix(), args.distribution());
+    if !args.skip_load {
+        let elapsed = load(&args)?;
+        println!("Loaded {} records in {:.2}s", args.records, elapsed.as_secs_f64());
+    }
+    let report = if args.processes > 1 {
+        run_processes(&args)?
+    } else {
+        run_process(&args)?
+    };
+    print_report(&args, &report);
+    Ok(())
+}
@@ -1,208 +1,564 @@
-use shared_serve::{Operation, Request, Header, SHARED_MEMORY_SIZE, CAPACITY};
-use nix::sys::{mman, mman::ProtFlags, mman::MapFlags};
-use nix::fcntl::OFlag;
-use nix::sys::stat::Mode;
+use shared_serve::command::{self, ParseError, TextRequest};
+use shared_serve::{Client, ClientConfig, ClientError, Operation, ScanCursor, ScanPage, Stats, DEFAULT_SEGMENT_NAME};
+use clap::{Parser, Subcommand, ValueEnum};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{CompletionType, Context, Editor, Helper, Highlighter, Hinter, Validator};
+use serde_json::json;
 use std::error::Error;
-use std::mem::size_of;
-use std::num::NonZero;
-use std::io::{self, Write};
-
-fn setup_shared_memory_client() -> Result<*mut u8, Box<dyn Error>> {
-    let shm_fd = mman::shm_open(
-        "RequestQueue",
-        OFlag::O_RDWR,
-        Mode::empty(),
-    ).map_err(|e| format!("{}: Make sure the server is running", e))?;
-
-    let ptr = unsafe { 
-        mman::mmap(
-            None, 
-            NonZero::new(SHARED_MEMORY_SIZE).unwrap(), 
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, 
-            MapFlags::MAP_SHARED, 
-            shm_fd, 
-            0)? 
-    };
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
 
-    Ok(ptr.as_ptr() as *mut u8)
+/// Sends requests to a shared_serve server.
+#[derive(Parser)]
+struct Args {
+    /// Name of the shared memory segment of the server
+    #[arg(long, global = true, default_value = DEFAULT_SEGMENT_NAME)]
+    name: String,
+    /// Connect through the server's Unix domain socket at this path instead of its shared memory
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+    /// Connect over TCP to a server listening on this address, e.g. db-host:7878
+    #[arg(long, global = true, conflicts_with = "socket")]
+    tcp: Option<String>,
+    /// Seconds to wait for the queue lock or a free reply slot
+    #[arg(long, global = true, default_value = "5", value_parser = parse_seconds)]
+    lock_timeout: Duration,
+    /// Seconds to wait for the server to answer a request
+    #[arg(long, global = true, default_value = "5", value_parser = parse_seconds)]
+    reply_timeout: Duration,
+    /// Attempts to reattach to a restarted server before giving up, 0 to never reconnect [default: 20]
+    #[arg(long, global = true)]
+    reconnect_attempts: Option<u32>,
+    /// Format of results and errors on stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Same as the `batch` command, kept for existing scripts
+    #[arg(long, hide = true)]
+    stress_test: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn add_request(ptr: *mut u8, request: Request) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        let header_ptr = ptr as *mut Header;
-        let header = &mut *header_ptr;
+#[derive(Subcommand)]
+enum Command {
+    /// Read requests at a prompt with line editing, history and tab completion. This is the default
+    Interactive {
+        /// File to keep the command history in [default: ~/.shared_serve_history]
+        #[arg(long)]
+        history: Option<PathBuf>,
+        /// Neither read nor write a history file
+        #[arg(long, conflicts_with = "history")]
+        no_history: bool,
+    },
+    /// Run one request per line, e.g. `INSERT mykey myvalue` or `{"op":"GET","key":"mykey"}`,
+    /// until `exit` or the end of input. Exits with 2 if any request failed
+    #[command(visible_alias = "import")]
+    Batch {
+        /// File of requests to run instead of stdin, e.g. written by `export`
+        file: Option<PathBuf>,
+    },
+    /// Print the value of a key. Exits with 1 if the key is not found
+    Get { key: String },
+    /// Insert a key or replace its value
+    Insert { key: String, value: String },
+    /// Delete a key. Exits with 1 if the key is not found
+    Delete { key: String },
+    /// Print the value of a key each time it changes, by polling the server
+    Subscribe {
+        key: String,
+        /// Milliseconds between polls
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+        /// Exit after this many changes, counting the value found first
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Print the server's counters
+    Stats,
+    /// List every key, one per line as a token of the batch format
+    Keys,
+    /// Write every key and its value as JSON Lines of INSERT requests, which `import` reads back
+    Export {
+        /// File to write instead of stdout
+        file: Option<PathBuf>,
+    },
+}
 
-        loop {
-            // Try to acquire write lock
-            let write_index_result = header.write_index.try_write();
-            if write_index_result.is_err() {
-                println!("Client: Waiting for write lock on write index");
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                continue;
-            }
-            let mut write_index_guard = write_index_result.unwrap();
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    /// One JSON object per result or error
+    Json,
+}
 
-            // Try to acquire read lock
-            let read_index_result = header.read_index.try_read();
-            if read_index_result.is_err() {
-                println!("Client: Waiting for read lock on read index");
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                continue;
-            }
-            let read_index_guard = read_index_result.unwrap();
-            let next_write = (*write_index_guard + 1) % CAPACITY;
-            
-            if next_write == *read_index_guard {
-                return Err("Client: Queue is full".into());
-            }
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+/// Exit status of a command whose key was found.
+const EXIT_HIT: i32 = 0;
+/// Exit status of a command whose key was not found.
+const EXIT_MISS: i32 = 1;
+/// Exit status of a command that failed.
+const EXIT_ERROR: i32 = 2;
+
+/// The server's answer to one request.
+enum Reply {
+    Inserted,
+    Value(Option<String>),
+    Deleted(bool),
+    Stats(Stats),
+    Keys(Vec<String>),
+    /// One page of keys, from a SCAN at the REPL.
+    Page(ScanPage),
+}
+
+impl Reply {
+    fn is_hit(&self) -> bool {
+        !matches!(self, Reply::Value(None) | Reply::Deleted(false))
+    }
+}
+
+/// Sends one request and waits for the server's answer.
+fn run_request(client: &Client, operation: Operation, key: &str, value: &str) -> Result<Reply, ClientError> {
+    Ok(match operation {
+        Operation::INSERT => {
+            client.insert(key, value)?;
+            Reply::Inserted
+        },
+        Operation::GET => Reply::Value(client.get(key)?),
+        Operation::DELETE => Reply::Deleted(client.delete(key)?),
+        Operation::STATS => Reply::Stats(client.stats()?),
+        Operation::SCAN => Reply::Keys(client.keys()?),
+    })
+}
+
+/// Writes results and errors in the chosen format.
+///
+/// JSON objects all go to stdout, errors included, so a tool reading them
+/// sees every outcome in order.
+struct Printer {
+    format: OutputFormat,
+    /// Print only values and `OK` for scripts, instead of the labelled lines of the interactive modes.
+    bare: bool,
+}
+
+impl Printer {
+    /// Prints the outcome of a request and returns the matching exit status.
+    fn reply(&self, operation: Operation, key: &str, result: &Result<Reply, ClientError>) -> i32 {
+        match self.format {
+            OutputFormat::Text => self.text(key, result),
+            OutputFormat::Json => println!("{}", Self::json(operation, key, result)),
+        }
+        match result {
+            Ok(reply) if reply.is_hit() => EXIT_HIT,
+            Ok(_) => EXIT_MISS,
+            Err(_) => EXIT_ERROR,
+        }
+    }
+
+    fn text(&self, key: &str, result: &Result<Reply, ClientError>) {
+        match result {
+            Ok(Reply::Inserted) | Ok(Reply::Deleted(true)) if self.bare => println!("OK"),
+            Ok(Reply::Value(Some(value))) if self.bare => println!("{}", value),
+            Ok(Reply::Value(None)) | Ok(Reply::Deleted(false)) if self.bare => {},
+            Err(e) if self.bare => eprintln!("Error: {}", e),
+            Ok(Reply::Inserted) => println!("OK"),
+            Ok(Reply::Value(Some(value))) => println!("Value: {}", value),
+            Ok(Reply::Value(None)) | Ok(Reply::Deleted(false)) => println!("Key not found: {}", key),
+            Ok(Reply::Deleted(true)) => println!("Deleted: {}", key),
+            Ok(Reply::Stats(stats)) => println!("{}", stats),
+            Ok(Reply::Keys(keys)) => {
+                for key in keys {
+                    println!("{}", command::quote(key));
+                }
+            },
+            Ok(Reply::Page(page)) => {
+                for key in &page.keys {
+                    println!("{}", command::quote(key));
+                }
+                if let Some(next) = page.next {
+                    println!("Next cursor: {}", next);
+                }
+            },
+            Err(e) => println!("Failed to add request: {}", e),
+        }
+    }
+
+    fn json(operation: Operation, key: &str, result: &Result<Reply, ClientError>) -> serde_json::Value {
+        let mut object = json!({ "operation": format!("{:?}", operation).to_lowercase() });
+        if !matches!(operation, Operation::STATS | Operation::SCAN) {
+            object["key"] = key.into();
+        }
+        match result {
+            Ok(reply) if !reply.is_hit() => object["status"] = "not_found".into(),
+            Ok(reply) => {
+                object["status"] = "ok".into();
+                match reply {
+                    Reply::Value(value) => object["value"] = value.as_deref().into(),
+                    Reply::Stats(stats) => object["stats"] = json!(stats),
+                    Reply::Keys(keys) => object["keys"] = json!(keys),
+                    Reply::Page(page) => {
+                        object["keys"] = json!(page.keys);
+                        object["next"] = page.next.map(|next| next.to_string()).into();
+                    },
+                    _ => {},
+                }
+            },
+            Err(e) => {
+                object["status"] = "error".into();
+                object["error"] = e.to_string().into();
+            },
+        }
+        object
+    }
+
+    /// Prints the outcome of a request entered at the REPL with the time it took,
+    /// quoting values so that whitespace in them shows.
+    fn timed(&self, operation: Operation, key: &str, result: &Result<Reply, ClientError>, elapsed: Duration) {
+        let milliseconds = elapsed.as_secs_f64() * 1000.0;
+        if self.format == OutputFormat::Json {
+            let mut object = Self::json(operation, key, result);
+            object["latency_ms"] = milliseconds.into();
+            println!("{}", object);
+            return;
+        }
+        let outcome = match result {
+            Ok(Reply::Inserted) | Ok(Reply::Deleted(true)) => "OK".to_string(),
+            Ok(Reply::Value(Some(value))) => command::quote(value),
+            Ok(Reply::Value(None)) | Ok(Reply::Deleted(false)) => "(not found)".to_string(),
+            // Below the block rather than after its last line
+            Ok(Reply::Stats(stats)) => format!("{}\n", stats),
+            Ok(Reply::Keys(keys)) => keys.iter().map(|key| command::quote(key)).collect::<Vec<_>>().join(" "),
+            Ok(Reply::Page(page)) => {
+                let keys: Vec<String> = page.keys.iter().map(|key| command::quote(key)).collect();
+                let next = page.next.map_or("end".to_string(), |next| format!("next {}", next));
+                format!("{} [{}]", keys.join(" "), next).trim_start().to_string()
+            },
+            Err(e) => format!("Error: {}", e),
+        };
+        let separator = if outcome.ends_with('\n') { "" } else { " " };
+        println!("{}{}({:.2} ms)", outcome, separator, milliseconds);
+    }
+
+    /// Prints an error that is not the outcome of a request, e.g. a malformed line.
+    fn error(&self, message: &str) {
+        match self.format {
+            OutputFormat::Text if self.bare => eprintln!("Error: {}", message),
+            OutputFormat::Text => println!("{}", message),
+            OutputFormat::Json => println!("{}", json!({ "status": "error", "error": message })),
+        }
+    }
+}
+
+/// Commands the REPL completes at the start of each `;` separated command.
+const REPL_COMMANDS: [&str; 9] = ["INSERT", "GET", "DELETE", "STATS", "KEYS", "SCAN", "HELP", "EXIT", "QUIT"];
 
-            // Calculate where to write the new request
-            let requests_ptr = (ptr as *mut u8).add(size_of::<Header>());
-            let request_slot = requests_ptr.add(*write_index_guard * size_of::<Request>());
-            
-            // Write the request
-            std::ptr::copy_nonoverlapping(
-                &request as *const Request as *const u8,
-                request_slot,
-                size_of::<Request>()
-            );
+const REPL_PROMPT: &str = "shared_serve> ";
 
-            println!("Client: Inserted request at position {} - {}", next_write, request);
-            
-            *write_index_guard = next_write;
+const REPL_HELP: &str = "\
+Commands, several of which can be separated by `;`:
+  INSERT <key> <value>   Insert a key or replace its value
+  GET <key>              Print the value of a key
+  DELETE <key>           Delete a key
+  STATS                  Print the server's counters
+  KEYS                   List every key
+  SCAN [<cursor>]        List a page of keys and the cursor of the next one
+  HELP                   Print this help
+  EXIT, QUIT             Leave, as does Ctrl-D
+Quote keys and values containing spaces, e.g. INSERT greeting \"hello world\".
+hex:<digits> and base64:<text> are decoded. Tab completes commands and the
+arrow keys walk through the history.";
 
-            return Ok(());
+fn default_history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".shared_serve_history"))
+}
+
+/// Completes the command word at the start of each command of the line.
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let command = &line[line[..pos].rfind(';').map_or(0, |i| i + 1)..pos];
+        let word = command.trim_start();
+        if word.contains(char::is_whitespace) {
+            // Keys and values are not completed
+            return Ok((pos, Vec::new()));
         }
+        // Answer in the case the user started typing in
+        let lowercase = word.chars().any(char::is_lowercase);
+        let candidates = REPL_COMMANDS
+            .iter()
+            .filter(|candidate| candidate.starts_with(&word.to_uppercase()))
+            .map(|candidate| if lowercase { candidate.to_lowercase() } else { candidate.to_string() })
+            .collect();
+        Ok((pos - word.len(), candidates))
     }
 }
 
-fn process_interactive_mode(ptr: *mut u8) -> Result<(), Box<dyn Error>> {
-    loop {
-        println!("\nAvailable operations:");
-        println!("1. INSERT");
-        println!("2. GET");
-        println!("3. DELETE");
-        println!("4. Exit");
-        
-        print!("Enter operation number: ");
-        io::stdout().flush()?;
-        
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        
-        let operation = match input.trim() {
-            "1" => Operation::INSERT,
-            "2" => Operation::GET,
-            "3" => Operation::DELETE,
-            "4" => break,
-            _ => {
-                println!("Invalid operation! Please try again.");
-                continue;
-            }
+/// One `;` separated command of a REPL line.
+enum ReplCommand {
+    Request(TextRequest),
+    Scan(ScanCursor),
+    Help,
+    Exit,
+}
+
+/// Parses every command of the line before any of them runs, so a typo doesn't leave it half done.
+fn parse_repl_line(line: &str) -> Result<Vec<ReplCommand>, ParseError> {
+    command::split_commands(line)?
+        .iter()
+        .map(|tokens| match tokens[0].to_uppercase().as_str() {
+            // Lists every key, like the `keys` command
+            "KEYS" if tokens.len() == 1 => Ok(ReplCommand::Request(TextRequest { operation: Operation::SCAN, key: String::new(), value: String::new() })),
+            "KEYS" => Err(ParseError::Usage("KEYS takes no arguments")),
+            "SCAN" => match &tokens[1..] {
+                [] => Ok(ReplCommand::Scan(ScanCursor::default())),
+                [cursor] => cursor.parse().map(ReplCommand::Scan).map_err(|_| ParseError::Usage("SCAN takes a cursor like 3:0 or none")),
+                _ => Err(ParseError::Usage("SCAN takes a cursor like 3:0 or none")),
+            },
+            "HELP" => Ok(ReplCommand::Help),
+            "EXIT" | "QUIT" => Ok(ReplCommand::Exit),
+            _ => TextRequest::from_tokens(tokens).map(ReplCommand::Request),
+        })
+        .collect()
+}
+
+fn process_interactive_mode(client: &Client, printer: &Printer, history: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let config = rustyline::Config::builder()
+        .max_history_size(1000)?
+        .history_ignore_dups(true)?
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::with_config(config)?;
+    editor.set_helper(Some(ReplHelper));
+    if let Some(path) = &history {
+        // There is none before the first session
+        let _ = editor.load_history(path);
+    }
+    if printer.format == OutputFormat::Text {
+        println!("Connected as client {}. Type HELP for the commands.", client.id());
+    }
+
+    'session: loop {
+        let line = match editor.readline(REPL_PROMPT) {
+            Ok(line) => line,
+            // Ctrl-C drops the line being edited
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
         };
-        
-        print!("Enter key: ");
-        io::stdout().flush()?;
-        let mut key = String::new();
-        io::stdin().read_line(&mut key)?;
-        let key = key.trim();
-        
-        let value = if operation == Operation::INSERT {
-            print!("Enter value: ");
-            io::stdout().flush()?;
-            let mut value = String::new();
-            io::stdin().read_line(&mut value)?;
-            value.trim().to_string()
-        } else {
-            "".to_string()
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        let commands = match parse_repl_line(&line) {
+            Ok(commands) => commands,
+            Err(e) => {
+                printer.error(&e.to_string());
+                continue;
+            },
         };
-        
-        let request = Request::new(operation, key, &value);
-        match add_request(ptr, request) {
-            Ok(_) => println!("Request added successfully!"),
-            Err(e) => println!("Failed to add request: {}", e),
+        for command in commands {
+            match command {
+                ReplCommand::Request(TextRequest { operation, key, value }) => {
+                    let started = Instant::now();
+                    let result = run_request(client, operation, &key, &value);
+                    printer.timed(operation, &key, &result, started.elapsed());
+                },
+                ReplCommand::Scan(cursor) => {
+                    let started = Instant::now();
+                    let result = client.scan(cursor).map(Reply::Page);
+                    printer.timed(Operation::SCAN, &cursor.to_string(), &result, started.elapsed());
+                },
+                ReplCommand::Help => println!("{}", REPL_HELP),
+                ReplCommand::Exit => break 'session,
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Failed to save history to {}: {}", path.display(), e);
         }
-        println!("================================================================");
     }
     Ok(())
 }
 
-fn process_stress_test_mode(ptr: *mut u8) -> Result<(), Box<dyn Error>> {
-    println!("Entering stress test mode. Format: <operation> <key> [value]");
-    println!("Operations: INSERT, GET, DELETE");
-    println!("Example: INSERT mykey myvalue");
-    println!("Example: GET mykey");
-    println!("Enter 'exit' to quit");
+/// Runs the requests read from `input` and returns whether all of them succeeded.
+/// Errors of a file name the line they occurred on.
+fn process_batch_mode(client: &Client, printer: &Printer, input: Box<dyn BufRead>, from_file: bool) -> Result<bool, Box<dyn Error>> {
+    if printer.format == OutputFormat::Text && !from_file {
+        println!("Entering batch mode. Format: <operation> <key> [value]");
+        println!("Quote values containing spaces. hex:<digits> and base64:<text> are decoded");
+        println!("Operations: INSERT, GET, DELETE, STATS");
+        println!("Example: INSERT mykey myvalue");
+        println!("Example: INSERT greeting \"hello world\"");
+        println!("Example: {{\"op\":\"GET\",\"key\":\"mykey\"}}");
+        println!("Enter 'exit' to quit");
+    }
 
-    loop {
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim();
+    let mut succeeded = true;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        // Only drop the line break, as a trailing space may be escaped
+        let line = line.trim_end_matches('\r');
 
-        if input.eq_ignore_ascii_case("exit") {
+        if line.trim().eq_ignore_ascii_case("exit") {
             break;
         }
 
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        if parts.is_empty() {
-            continue;
-        }
-
-        let operation = match parts[0].to_uppercase().as_str() {
-            "INSERT" => {
-                if parts.len() != 3 {
-                    println!("INSERT requires key and value");
-                    continue;
+        match command::parse_line(line) {
+            Ok(requests) => {
+                for TextRequest { operation, key, value } in requests {
+                    let result = run_request(client, operation, &key, &value);
+                    succeeded &= result.is_ok();
+                    printer.reply(operation, &key, &result);
                 }
-                Operation::INSERT
             },
-            "GET" => {
-                if parts.len() != 2 {
-                    println!("GET requires key");
-                    continue;
-                }
-                Operation::GET
+            Err(e) if from_file => {
+                succeeded = false;
+                printer.error(&format!("Line {}: {}", number + 1, e));
             },
-            "DELETE" => {
-                if parts.len() != 2 {
-                    println!("DELETE requires key");
-                    continue;
-                }
-                Operation::DELETE
+            Err(e) => {
+                succeeded = false;
+                printer.error(&e.to_string());
             },
-            _ => {
-                println!("Invalid operation: {}", parts[0]);
-                continue;
-            }
-        };
+        }
+    }
+    Ok(succeeded)
+}
 
-        let key = parts[1];
-        let value = if operation == Operation::INSERT {
-            parts[2].to_string()
-        } else {
-            "".to_string()
-        };
+/// Writes an INSERT request for every key to `output` and returns how many were written.
+/// Keys deleted after they were listed are left out.
+fn export(client: &Client, output: &mut dyn Write) -> Result<usize, Box<dyn Error>> {
+    let mut exported = 0;
+    for key in client.keys()? {
+        if let Some(value) = client.get(&key)? {
+            writeln!(output, "{}", TextRequest { operation: Operation::INSERT, key, value }.to_json())?;
+            exported += 1;
+        }
+    }
+    output.flush()?;
+    Ok(exported)
+}
 
-        let request = Request::new(operation, key, &value);
-        match add_request(ptr, request) {
-            Ok(_) => println!("Request added successfully!"),
-            Err(e) => println!("Failed to add request: {}", e),
+/// Prints the value of the key, then polls it and prints it again whenever it changed.
+fn subscribe(client: &Client, printer: &Printer, key: &str, interval: Duration, count: Option<usize>) -> i32 {
+    let mut last = None;
+    let mut changes = 0;
+    loop {
+        let value = match client.get(key) {
+            Ok(value) => value,
+            Err(e) => return printer.reply(Operation::GET, key, &Err(e)),
+        };
+        if last.as_ref() != Some(&value) {
+            printer.reply(Operation::GET, key, &Ok(Reply::Value(value.clone())));
+            last = Some(value);
+            changes += 1;
+            if count.is_some_and(|count| changes >= count) {
+                return EXIT_HIT;
+            }
         }
+        thread::sleep(interval);
     }
-    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let stress_test_mode = args.len() > 1 && args[1] == "--stress-test";
+/// Runs the command and returns the exit status, after the client disconnected.
+fn run(args: Args) -> i32 {
+    let command = match args.command {
+        Some(command) => command,
+        None if args.stress_test => Command::Batch { file: None },
+        None => Command::Interactive { history: None, no_history: false },
+    };
+    let printer = Printer {
+        format: args.output,
+        bare: matches!(
+            command,
+            Command::Get { .. } | Command::Insert { .. } | Command::Delete { .. } | Command::Stats | Command::Keys
+        ),
+    };
 
-    let ptr = setup_shared_memory_client()?;
-    
-    if stress_test_mode {
-        process_stress_test_mode(ptr)?;
-    } else {
-        process_interactive_mode(ptr)?;
+    let mut config = ClientConfig {
+        lock_timeout: args.lock_timeout,
+        reply_timeout: args.reply_timeout,
+        ..ClientConfig::default()
+    };
+    if let Some(attempts) = args.reconnect_attempts {
+        config.reconnect.max_attempts = attempts;
     }
-    
-    Ok(())
+    let connected = match (&args.socket, &args.tcp) {
+        (Some(path), _) => Client::connect_socket_with(path, config),
+        (None, Some(address)) => Client::connect_tcp_with(address.as_str(), config),
+        (None, None) => Client::connect_with(&args.name, config),
+    };
+    let client = match connected {
+        Ok(client) => client,
+        Err(e) => {
+            printer.error(&e.to_string());
+            return EXIT_ERROR;
+        }
+    };
+
+    let session = match command {
+        Command::Interactive { history, no_history } => {
+            let history = if no_history { None } else { history.or_else(default_history_file) };
+            process_interactive_mode(&client, &printer, history).map(|()| EXIT_HIT)
+        },
+        Command::Batch { file: None } => process_batch_mode(&client, &printer, Box::new(io::stdin().lock()), false)
+            .map(|succeeded| if succeeded { EXIT_HIT } else { EXIT_ERROR }),
+        Command::Batch { file: Some(path) } => File::open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e).into())
+            .and_then(|file| process_batch_mode(&client, &printer, Box::new(BufReader::new(file)), true))
+            .map(|succeeded| if succeeded { EXIT_HIT } else { EXIT_ERROR }),
+        Command::Get { key } => return printer.reply(Operation::GET, &key, &run_request(&client, Operation::GET, &key, "")),
+        Command::Insert { key, value } => {
+            return printer.reply(Operation::INSERT, &key, &run_request(&client, Operation::INSERT, &key, &value))
+        },
+        Command::Delete { key } => {
+            return printer.reply(Operation::DELETE, &key, &run_request(&client, Operation::DELETE, &key, ""))
+        },
+        Command::Stats => return printer.reply(Operation::STATS, "", &run_request(&client, Operation::STATS, "", "")),
+        Command::Subscribe { key, interval, count } => {
+            return subscribe(&client, &printer, &key, Duration::from_millis(interval), count)
+        },
+        Command::Keys => return printer.reply(Operation::SCAN, "", &run_request(&client, Operation::SCAN, "", "")),
+        Command::Export { file: None } => export(&client, &mut io::stdout().lock()).map(|exported| {
+            if printer.format == OutputFormat::Text {
+                eprintln!("Exported {} keys", exported);
+            }
+            EXIT_HIT
+        }),
+        Command::Export { file: Some(path) } => File::create(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e).into())
+            .and_then(|file| export(&client, &mut BufWriter::new(file)))
+            .map(|exported| {
+                match printer.format {
+                    OutputFormat::Text => println!("Exported {} keys to {}", exported, path.display()),
+                    OutputFormat::Json => println!("{}", json!({ "status": "ok", "exported": exported })),
+                }
+                EXIT_HIT
+            }),
+    };
+    match session {
+        Ok(status) => status,
+        Err(e) => {
+            printer.error(&e.to_string());
+            EXIT_ERROR
+        }
+    }
+}
+
+fn main() {
+    std::process::exit(run(Args::parse()));
 }
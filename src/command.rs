@@ -0,0 +1,372 @@
+use crate::Operation;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Prefix of an unquoted token holding hex encoded bytes, e.g. `hex:68690a`.
+pub const HEX_PREFIX: &str = "hex:";
+/// Prefix of an unquoted token holding base64 encoded bytes, e.g. `base64:aGkK`.
+pub const BASE64_PREFIX: &str = "base64:";
+
+/// Why a line of the client's text protocol could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A quote opened with the given character is never closed.
+    UnterminatedQuote(char),
+    /// The line ends with a backslash that escapes nothing.
+    TrailingBackslash,
+    InvalidEscape(String),
+    InvalidHex(String),
+    InvalidBase64(String),
+    /// A token decoded to bytes that are not UTF-8, which the server cannot store.
+    NotUtf8,
+    /// A token contains a NUL byte, which would cut it short in a request.
+    NulByte,
+    UnknownOperation(String),
+    /// The operation got the wrong number of arguments. Holds what it requires.
+    Usage(&'static str),
+    /// A line starting with `{` is not a valid JSON request.
+    Json(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedQuote(quote) => write!(f, "Missing closing quote {}", quote),
+            ParseError::TrailingBackslash => write!(f, "Backslash at the end of the line"),
+            ParseError::InvalidEscape(escape) => write!(f, "Invalid escape sequence {}", escape),
+            ParseError::InvalidHex(text) => write!(f, "Invalid hex literal: {}", text),
+            ParseError::InvalidBase64(text) => write!(f, "Invalid base64 literal: {}", text),
+            ParseError::NotUtf8 => write!(f, "Keys and values must be valid UTF-8"),
+            ParseError::NulByte => write!(f, "Keys and values must not contain NUL bytes"),
+            ParseError::UnknownOperation(operation) => write!(f, "Invalid operation: {}", operation),
+            ParseError::Usage(usage) => write!(f, "{}", usage),
+            ParseError::Json(e) => write!(f, "Invalid JSON request: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A request written as `<operation> [key] [value]`, e.g. `INSERT greeting "hello world"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRequest {
+    pub operation: Operation,
+    pub key: String,
+    pub value: String,
+}
+
+/// The operations of the text protocol with their number of arguments and usage.
+fn operation_named(name: &str) -> Option<(Operation, usize, &'static str)> {
+    match name.to_uppercase().as_str() {
+        "INSERT" => Some((Operation::INSERT, 2, "INSERT requires key and value")),
+        "GET" => Some((Operation::GET, 1, "GET requires key")),
+        "DELETE" => Some((Operation::DELETE, 1, "DELETE requires key")),
+        "STATS" => Some((Operation::STATS, 0, "STATS takes no arguments")),
+        _ => None,
+    }
+}
+
+/// A request as a JSON object, e.g. `{"op":"INSERT","key":"k","value":"v"}`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRequest {
+    op: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+impl TextRequest {
+    /// Checks the operation and its number of arguments.
+    pub fn from_tokens(tokens: &[String]) -> Result<Self, ParseError> {
+        let name = tokens.first().ok_or(ParseError::Usage("Missing operation"))?;
+        let (operation, arguments, usage) =
+            operation_named(name).ok_or_else(|| ParseError::UnknownOperation(name.clone()))?;
+        if tokens.len() != arguments + 1 {
+            return Err(ParseError::Usage(usage));
+        }
+        let argument = |index: usize| tokens.get(index).cloned().unwrap_or_default();
+        Ok(TextRequest { operation, key: argument(1), value: argument(2) })
+    }
+
+    /// Parses a request written as a JSON object. `key` and `value` are
+    /// required where the operation takes them and must be left out otherwise.
+    pub fn from_json(line: &str) -> Result<Self, ParseError> {
+        let request: JsonRequest = serde_json::from_str(line).map_err(|e| ParseError::Json(e.to_string()))?;
+        let (operation, arguments, usage) =
+            operation_named(&request.op).ok_or(ParseError::UnknownOperation(request.op))?;
+        let given = [&request.key, &request.value];
+        if given.iter().take(arguments).any(|argument| argument.is_none())
+            || given.iter().skip(arguments).any(|argument| argument.is_some())
+        {
+            return Err(ParseError::Usage(usage));
+        }
+        let key = request.key.unwrap_or_default();
+        let value = request.value.unwrap_or_default();
+        if key.contains('\0') || value.contains('\0') {
+            return Err(ParseError::NulByte);
+        }
+        Ok(TextRequest { operation, key, value })
+    }
+
+    /// Writes the request as the JSON object `from_json` reads.
+    pub fn to_json(&self) -> String {
+        let arguments = match self.operation {
+            Operation::INSERT => 2,
+            Operation::GET | Operation::DELETE | Operation::SCAN => 1,
+            Operation::STATS => 0,
+        };
+        let request = JsonRequest {
+            op: format!("{:?}", self.operation),
+            key: (arguments > 0).then(|| self.key.clone()),
+            value: (arguments > 1).then(|| self.value.clone()),
+        };
+        serde_json::to_string(&request).unwrap()
+    }
+}
+
+impl fmt::Display for TextRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.operation)?;
+        match self.operation {
+            Operation::INSERT => write!(f, " {} {}", quote(&self.key), quote(&self.value)),
+            Operation::GET | Operation::DELETE | Operation::SCAN => write!(f, " {}", quote(&self.key)),
+            Operation::STATS => Ok(()),
+        }
+    }
+}
+
+/// Parses the requests of one line: a JSON object, or text requests
+/// separated by `;`. A blank line holds no requests.
+pub fn parse_line(line: &str) -> Result<Vec<TextRequest>, ParseError> {
+    if line.trim_start().starts_with('{') {
+        return Ok(vec![TextRequest::from_json(line)?]);
+    }
+    split_commands(line)?.iter().map(|tokens| TextRequest::from_tokens(tokens)).collect()
+}
+
+/// Splits a line into tokens, treating an unquoted `;` like whitespace.
+/// See `split_commands` for the syntax.
+pub fn tokenize(line: &str) -> Result<Vec<String>, ParseError> {
+    Ok(split_commands(line)?.concat())
+}
+
+/// Splits a line into commands at unquoted `;` and each command into tokens
+/// at unquoted whitespace. Commands without tokens are left out.
+///
+/// - `'...'` keeps everything up to the next single quote as is.
+/// - `"..."` and unquoted text understand the escapes `\\`, `\"`, `\'`, `\n`,
+///   `\r`, `\t`, `\xHH` and, outside quotes, a backslash before a space or `;`.
+/// - Quoted and unquoted parts next to each other form one token.
+/// - An unquoted token starting with `hex:` or `base64:` is decoded.
+pub fn split_commands(line: &str) -> Result<Vec<Vec<String>>, ParseError> {
+    let mut commands = vec![Vec::new()];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None => break,
+            Some(';') => {
+                chars.next();
+                commands.push(Vec::new());
+            },
+            Some(_) => commands.last_mut().unwrap().push(token(&mut chars)?),
+        }
+    }
+    commands.retain(|tokens| !tokens.is_empty());
+    Ok(commands)
+}
+
+fn token(chars: &mut Peekable<Chars>) -> Result<String, ParseError> {
+    let mut bytes = Vec::new();
+    // Only tokens without quotes or escapes can be binary literals
+    let mut plain = true;
+    while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != ';') {
+        match c {
+            '\'' => {
+                plain = false;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => push_char(&mut bytes, c),
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                    }
+                }
+            },
+            '"' => {
+                plain = false;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => escape(chars, &mut bytes)?,
+                        Some(c) => push_char(&mut bytes, c),
+                        None => return Err(ParseError::UnterminatedQuote('"')),
+                    }
+                }
+            },
+            '\\' => {
+                plain = false;
+                escape(chars, &mut bytes)?;
+            },
+            c => push_char(&mut bytes, c),
+        }
+    }
+
+    let bytes = if plain { decode_literal(bytes)? } else { bytes };
+    let text = String::from_utf8(bytes).map_err(|_| ParseError::NotUtf8)?;
+    if text.contains('\0') {
+        return Err(ParseError::NulByte);
+    }
+    Ok(text)
+}
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Decodes the escape sequence following a backslash.
+fn escape(chars: &mut Peekable<Chars>, bytes: &mut Vec<u8>) -> Result<(), ParseError> {
+    let c = chars.next().ok_or(ParseError::TrailingBackslash)?;
+    match c {
+        'n' => bytes.push(b'\n'),
+        'r' => bytes.push(b'\r'),
+        't' => bytes.push(b'\t'),
+        '\\' | '"' | '\'' | ' ' | ';' => bytes.push(c as u8),
+        'x' => {
+            let digits: String = (0..2).filter_map(|_| chars.next_if(char::is_ascii_hexdigit)).collect();
+            if digits.len() != 2 {
+                return Err(ParseError::InvalidEscape(format!("\\x{}", digits)));
+            }
+            bytes.push(u8::from_str_radix(&digits, 16).unwrap());
+        },
+        c => return Err(ParseError::InvalidEscape(format!("\\{}", c))),
+    }
+    Ok(())
+}
+
+fn decode_literal(bytes: Vec<u8>) -> Result<Vec<u8>, ParseError> {
+    // Plain tokens were built from whole chars
+    let text = std::str::from_utf8(&bytes).unwrap();
+    if let Some(hex) = text.strip_prefix(HEX_PREFIX) {
+        decode_hex(hex).ok_or_else(|| ParseError::InvalidHex(text.to_string()))
+    } else if let Some(base64) = text.strip_prefix(BASE64_PREFIX) {
+        BASE64.decode(base64).map_err(|_| ParseError::InvalidBase64(text.to_string()))
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// Writes the text as a token that `tokenize` reads back unchanged,
+/// quoting and escaping it only where needed.
+pub fn quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && !text.starts_with(HEX_PREFIX)
+        && !text.starts_with(BASE64_PREFIX)
+        && !text.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '\\' | ';'));
+    if plain {
+        return text.to_string();
+    }
+
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Unit tests for the text protocol
+#[test]
+fn test_tokenize_quotes_and_escapes() {
+    let tokens = tokenize(r#"INSERT 'my key' "hello \"big\"\tworld" a\ b "x"'y'z \x41"#).unwrap();
+    assert_eq!(tokens, ["INSERT", "my key", "hello \"big\"\tworld", "a b", "xyz", "A"]);
+    assert_eq!(tokenize(r#"'it''s' 'C:\dir' """#).unwrap(), ["its", r"C:\dir", ""]);
+    assert_eq!(tokenize("  \t ").unwrap(), Vec::<String>::new());
+    assert_eq!(tokenize(r#""caf\xc3\xa9""#).unwrap(), ["café"]);
+
+    assert_eq!(tokenize("GET \"open"), Err(ParseError::UnterminatedQuote('"')));
+    assert_eq!(tokenize("GET 'open"), Err(ParseError::UnterminatedQuote('\'')));
+    assert_eq!(tokenize("GET key\\"), Err(ParseError::TrailingBackslash));
+    assert_eq!(tokenize(r"GET \q"), Err(ParseError::InvalidEscape("\\q".to_string())));
+    assert_eq!(tokenize(r#""\x4""#), Err(ParseError::InvalidEscape("\\x4".to_string())));
+    assert_eq!(tokenize(r#""\xff""#), Err(ParseError::NotUtf8));
+    assert_eq!(tokenize(r#""a\x00b""#), Err(ParseError::NulByte));
+}
+
+#[test]
+fn test_tokenize_binary_literals() {
+    assert_eq!(tokenize("hex:68656c6c6f base64:d29ybGQ= hex:").unwrap(), ["hello", "world", ""]);
+    assert_eq!(tokenize("HEX:00").unwrap(), ["HEX:00"]);
+    // Quoting keeps the prefix as text
+    assert_eq!(tokenize("'hex:41' \"base64:QQ==\"").unwrap(), ["hex:41", "base64:QQ=="]);
+    assert_eq!(tokenize("hex:4"), Err(ParseError::InvalidHex("hex:4".to_string())));
+    assert_eq!(tokenize("hex:zz"), Err(ParseError::InvalidHex("hex:zz".to_string())));
+    assert_eq!(tokenize("base64:!"), Err(ParseError::InvalidBase64("base64:!".to_string())));
+    assert_eq!(tokenize("hex:ff"), Err(ParseError::NotUtf8));
+    assert_eq!(tokenize("base64:AA=="), Err(ParseError::NulByte));
+}
+
+#[test]
+fn test_parse_line() {
+    let request = parse_line("insert greeting \"hello world\"").unwrap().remove(0);
+    assert_eq!(request, TextRequest { operation: Operation::INSERT, key: "greeting".into(), value: "hello world".into() });
+    assert_eq!(request.to_string(), "INSERT greeting \"hello world\"");
+    assert_eq!(parse_line("STATS").unwrap()[0].operation, Operation::STATS);
+    assert_eq!(parse_line("   ").unwrap(), vec![]);
+    assert_eq!(parse_line("INSERT key"), Err(ParseError::Usage("INSERT requires key and value")));
+    assert_eq!(parse_line("GET a b"), Err(ParseError::Usage("GET requires key")));
+    assert_eq!(parse_line("PUT a b"), Err(ParseError::UnknownOperation("PUT".to_string())));
+}
+
+#[test]
+fn test_split_commands() {
+    let requests = parse_line("INSERT a 1; GET a;;DELETE 'semi;colon' ; ").unwrap();
+    let operations: Vec<Operation> = requests.iter().map(|request| request.operation).collect();
+    assert_eq!(operations, [Operation::INSERT, Operation::GET, Operation::DELETE]);
+    assert_eq!(requests[2].key, "semi;colon");
+    assert_eq!(split_commands(r"GET a\;b; GET c").unwrap(), vec![vec!["GET", "a;b"], vec!["GET", "c"]]);
+    assert_eq!(quote("a;b"), "\"a;b\"");
+    // One bad command rejects the whole line, so nothing runs half way
+    assert_eq!(parse_line("INSERT a 1; GET"), Err(ParseError::Usage("GET requires key")));
+    // JSON requests are not split
+    assert_eq!(parse_line(r#"{"op":"GET","key":"a;b"}"#).unwrap()[0].key, "a;b");
+}
+
+#[test]
+fn test_parse_json_line() {
+    let request = parse_line(r#" {"op":"insert","key":"k","value":"hello world"}"#).unwrap().remove(0);
+    assert_eq!(request, TextRequest { operation: Operation::INSERT, key: "k".into(), value: "hello world".into() });
+    assert_eq!(request.to_json(), r#"{"op":"INSERT","key":"k","value":"hello world"}"#);
+    assert_eq!(TextRequest::from_json(&request.to_json()), Ok(request));
+    let stats = parse_line(r#"{"op":"STATS"}"#).unwrap().remove(0);
+    assert_eq!(stats.to_json(), r#"{"op":"STATS"}"#);
+
+    assert_eq!(parse_line(r#"{"op":"GET"}"#), Err(ParseError::Usage("GET requires key")));
+    assert_eq!(parse_line(r#"{"op":"GET","key":"k","value":"v"}"#), Err(ParseError::Usage("GET requires key")));
+    assert_eq!(parse_line(r#"{"op":"INSERT","value":"v"}"#), Err(ParseError::Usage("INSERT requires key and value")));
+    assert_eq!(parse_line(r#"{"op":"PUT","key":"k"}"#), Err(ParseError::UnknownOperation("PUT".to_string())));
+    assert_eq!(parse_line(r#"{"op":"GET","key":"a\u0000"}"#), Err(ParseError::NulByte));
+    assert!(matches!(parse_line(r#"{"op":"GET","key":1}"#), Err(ParseError::Json(_))));
+    assert!(matches!(parse_line(r#"{"op":"GET","ke":"k"}"#), Err(ParseError::Json(_))));
+}
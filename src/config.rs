@@ -0,0 +1,400 @@
+use crate::logging::LogFormat;
+use crate::{Eviction, DEFAULT_CAPACITY, DEFAULT_SEGMENT_MODE, DEFAULT_SEGMENT_NAME, MAX_CAPACITY};
+use log::LevelFilter;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variables starting with this prefix override settings of the
+/// config file, e.g. `SHARED_SERVE_WORKERS_THREADS=8` sets `workers.threads`.
+pub const ENV_PREFIX: &str = "SHARED_SERVE_";
+
+/// Settings of the server, read from a TOML file with one table per section.
+///
+/// Every setting is optional. The server starts from the defaults, applies
+/// the config file, then the environment and finally its command line flags.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerConfig {
+    pub segment: SegmentConfig,
+    pub table: TableConfig,
+    pub workers: WorkersConfig,
+    pub persistence: PersistenceConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub socket: SocketConfig,
+    pub tcp: TcpConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentConfig {
+    /// Name of the shared memory object.
+    pub name: String,
+    /// Number of request slots in the ring, which holds one request less.
+    pub capacity: usize,
+    /// Permission bits of the shared memory object, e.g. `0o660` to admit the owner's group.
+    pub mode: u32,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        SegmentConfig {
+            name: DEFAULT_SEGMENT_NAME.to_string(),
+            capacity: DEFAULT_CAPACITY,
+            mode: DEFAULT_SEGMENT_MODE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableConfig {
+    /// Number of buckets.
+    pub size: usize,
+    /// Most keys the table holds, 0 for no limit.
+    pub max_keys: usize,
+    /// What happens to an INSERT of a new key once `max_keys` is reached.
+    pub eviction: Eviction,
+}
+
+impl Default for TableConfig {
+    fn default() -> Self {
+        TableConfig { size: 10, max_keys: 0, eviction: Eviction::Reject }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkersConfig {
+    pub threads: usize,
+}
+
+impl Default for WorkersConfig {
+    fn default() -> Self {
+        WorkersConfig { threads: 4 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// Keep the requests still queued in a segment left behind by a crashed server.
+    pub recover: bool,
+    /// Leave the segment and its pending requests in place on shutdown.
+    pub preserve_queue: bool,
+    /// Seconds to keep processing pending requests after a shutdown signal.
+    pub drain_timeout: u64,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        PersistenceConfig { recover: false, preserve_queue: false, drain_timeout: 5 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: LevelFilter::Info, format: LogFormat::Text }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serve Prometheus metrics on this port of 127.0.0.1.
+    pub port: Option<u16>,
+    /// Periodically write Prometheus metrics to this file.
+    pub file: Option<PathBuf>,
+    /// Seconds between writes of the metrics file.
+    pub interval: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig { port: None, file: None, interval: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// Also accept requests on a Unix domain socket at this path.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    /// Also accept requests over TCP on this address, e.g. `0.0.0.0:7878`.
+    pub address: Option<SocketAddr>,
+    /// Most TCP connections open at once, 0 for no limit.
+    pub max_connections: usize,
+    /// Seconds after which a connection that sent no request is closed, 0 to keep it open.
+    pub idle_timeout: u64,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig { address: None, max_connections: 64, idle_timeout: 300 }
+    }
+}
+
+impl ServerConfig {
+    /// Reads a config file. Problems are added to `errors` rather than
+    /// returned, so that they can be reported together with the ones found
+    /// later, and the settings that could be read are kept.
+    pub fn from_file(path: &Path, errors: &mut Vec<String>) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let mut file_errors = Vec::new();
+                let config = Self::parse(&text, &mut file_errors);
+                errors.extend(file_errors.into_iter().map(|e| format!("{}: {}", path.display(), e)));
+                config
+            }
+            Err(e) => {
+                errors.push(format!("{}: {}", path.display(), e));
+                ServerConfig::default()
+            }
+        }
+    }
+
+    /// Parses a config file. Each section is read on its own, and a section
+    /// with errors keeps its defaults.
+    pub fn parse(text: &str, errors: &mut Vec<String>) -> Self {
+        let mut config = ServerConfig::default();
+        let table: toml::Table = match toml::from_str(text) {
+            Ok(table) => table,
+            Err(e) => {
+                errors.push(e.message().to_string());
+                return config;
+            }
+        };
+        for (section, value) in table {
+            match section.as_str() {
+                "segment" => parse_section(&section, value, &mut config.segment, errors),
+                "table" => parse_section(&section, value, &mut config.table, errors),
+                "workers" => parse_section(&section, value, &mut config.workers, errors),
+                "persistence" => parse_section(&section, value, &mut config.persistence, errors),
+                "logging" => parse_section(&section, value, &mut config.logging, errors),
+                "metrics" => parse_section(&section, value, &mut config.metrics, errors),
+                "socket" => parse_section(&section, value, &mut config.socket, errors),
+                "tcp" => parse_section(&section, value, &mut config.tcp, errors),
+                _ => errors.push(format!("unknown section [{}]", section)),
+            }
+        }
+        config
+    }
+
+    /// Applies the `SHARED_SERVE_<SECTION>_<SETTING>` variables among `vars`,
+    /// usually `std::env::vars()`. Other variables are ignored. Returns the
+    /// names of the variables with the prefix that name no setting, so they
+    /// can be warned about without failing a server whose environment also
+    /// serves other tools.
+    pub fn apply_env<I: IntoIterator<Item = (String, String)>>(&mut self, vars: I, errors: &mut Vec<String>) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for (name, value) in vars {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let result = match setting {
+                "SEGMENT_NAME" => parse_env(&value).map(|v| self.segment.name = v),
+                "SEGMENT_CAPACITY" => parse_env(&value).map(|v| self.segment.capacity = v),
+                "SEGMENT_MODE" => parse_mode(&value).map(|v| self.segment.mode = v),
+                "TABLE_SIZE" => parse_env(&value).map(|v| self.table.size = v),
+                "TABLE_MAX_KEYS" => parse_env(&value).map(|v| self.table.max_keys = v),
+                "TABLE_EVICTION" => parse_env(&value).map(|v| self.table.eviction = v),
+                "WORKERS_THREADS" => parse_env(&value).map(|v| self.workers.threads = v),
+                "PERSISTENCE_RECOVER" => parse_env(&value).map(|v| self.persistence.recover = v),
+                "PERSISTENCE_PRESERVE_QUEUE" => parse_env(&value).map(|v| self.persistence.preserve_queue = v),
+                "PERSISTENCE_DRAIN_TIMEOUT" => parse_env(&value).map(|v| self.persistence.drain_timeout = v),
+                "LOGGING_LEVEL" => parse_env(&value).map(|v| self.logging.level = v),
+                "LOGGING_FORMAT" => parse_env(&value).map(|v| self.logging.format = v),
+                "METRICS_PORT" => parse_env(&value).map(|v| self.metrics.port = Some(v)),
+                "METRICS_FILE" => parse_env(&value).map(|v| self.metrics.file = Some(v)),
+                "METRICS_INTERVAL" => parse_env(&value).map(|v| self.metrics.interval = v),
+                "SOCKET_PATH" => parse_env(&value).map(|v| self.socket.path = Some(v)),
+                "TCP_ADDRESS" => parse_env(&value).map(|v| self.tcp.address = Some(v)),
+                "TCP_MAX_CONNECTIONS" => parse_env(&value).map(|v| self.tcp.max_connections = v),
+                "TCP_IDLE_TIMEOUT" => parse_env(&value).map(|v| self.tcp.idle_timeout = v),
+                _ => {
+                    unknown.push(name);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+        unknown
+    }
+
+    /// Adds every setting that parsed but can't work to `errors`.
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        let name = &self.segment.name;
+        if name.is_empty() || name.len() > 255 || name.trim_start_matches('/').contains('/') {
+            errors.push(format!("segment.name `{}` must be 1 to 255 characters without a `/` after the first", name));
+        }
+        if !(2..=MAX_CAPACITY).contains(&self.segment.capacity) {
+            errors.push(format!("segment.capacity {} must be between 2 and {}", self.segment.capacity, MAX_CAPACITY));
+        }
+        if self.segment.mode & !0o777 != 0 {
+            errors.push(format!("segment.mode {:#o} has bits outside of 0o777", self.segment.mode));
+        } else if self.segment.mode & 0o600 != 0o600 {
+            errors.push(format!("segment.mode {:#o} must let the owner read and write", self.segment.mode));
+        }
+        if self.table.size == 0 {
+            errors.push("table.size must be greater than 0".to_string());
+        }
+        if self.workers.threads == 0 {
+            errors.push("workers.threads must be greater than 0".to_string());
+        }
+        if self.metrics.interval == 0 {
+            errors.push("metrics.interval must be greater than 0".to_string());
+        }
+        if self.socket.path.as_ref().is_some_and(|path| path.as_os_str().is_empty()) {
+            errors.push("socket.path must not be empty".to_string());
+        }
+    }
+}
+
+fn parse_section<T: DeserializeOwned>(section: &str, value: toml::Value, target: &mut T, errors: &mut Vec<String>) {
+    match value.try_into() {
+        Ok(parsed) => *target = parsed,
+        Err(e) => errors.push(format!("[{}]: {}", section, e.message())),
+    }
+}
+
+fn parse_env<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value.parse().map_err(|e| format!("invalid value `{}`: {}", value, e))
+}
+
+/// Parses permission bits written in octal, with or without a `0o` or `0` prefix.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    u32::from_str_radix(digits, 8).map_err(|e| format!("invalid octal mode `{}`: {}", value, e))
+}
+
+// Unit tests for the config file
+#[test]
+fn test_parse_config_file() {
+    let mut errors = Vec::new();
+    let config = ServerConfig::parse(
+        r#"
+        [segment]
+        name = "Orders"
+        capacity = 1024
+        mode = 0o660
+
+        [table]
+        size = 64
+        max_keys = 1000
+        eviction = "evict-oldest"
+
+        [logging]
+        level = "debug"
+        format = "json"
+
+        [socket]
+        path = "/run/shared_serve.sock"
+
+        [tcp]
+        address = "127.0.0.1:7878"
+        idle_timeout = 0
+        "#,
+        &mut errors,
+    );
+    assert_eq!(errors, Vec::<String>::new());
+    assert_eq!(config.segment.name, "Orders");
+    assert_eq!(config.segment.capacity, 1024);
+    assert_eq!(config.segment.mode, 0o660);
+    assert_eq!(config.table, TableConfig { size: 64, max_keys: 1000, eviction: Eviction::EvictOldest });
+    assert_eq!(config.workers, WorkersConfig::default());
+    assert_eq!(config.logging, LoggingConfig { level: LevelFilter::Debug, format: LogFormat::Json });
+    assert_eq!(config.socket.path, Some(PathBuf::from("/run/shared_serve.sock")));
+    assert_eq!(config.tcp, TcpConfig { address: Some("127.0.0.1:7878".parse().unwrap()), max_connections: 64, idle_timeout: 0 });
+}
+
+#[test]
+fn test_parse_reports_every_section() {
+    let mut errors = Vec::new();
+    let config = ServerConfig::parse(
+        r#"
+        [segment]
+        name = "Orders"
+
+        [table]
+        size = "big"
+
+        [workers]
+        thread = 4
+
+        [cache]
+        "#,
+        &mut errors,
+    );
+    // The sections without errors still apply
+    assert_eq!(config.segment.name, "Orders");
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors.iter().any(|e| e.starts_with("[table]")));
+    assert!(errors.iter().any(|e| e.starts_with("[workers]") && e.contains("thread")));
+    assert!(errors.contains(&"unknown section [cache]".to_string()));
+}
+
+#[test]
+fn test_env_overrides() {
+    let mut config = ServerConfig::default();
+    let vars = [
+        ("SHARED_SERVE_WORKERS_THREADS", "8"),
+        ("SHARED_SERVE_SEGMENT_MODE", "0o640"),
+        ("SHARED_SERVE_METRICS_PORT", "9100"),
+        ("SHARED_SERVE_TCP_ADDRESS", "[::1]:7878"),
+        ("SHARED_SERVE_TABLE_SIZE", "many"),
+        ("SHARED_SERVE_TABLE_COLOR", "red"),
+        ("PATH", "/usr/bin"),
+    ];
+    let mut errors = Vec::new();
+    let unknown = config.apply_env(vars.iter().map(|(name, value)| (name.to_string(), value.to_string())), &mut errors);
+    assert_eq!(config.workers.threads, 8);
+    assert_eq!(config.segment.mode, 0o640);
+    assert_eq!(config.metrics.port, Some(9100));
+    assert_eq!(config.tcp.address, Some("[::1]:7878".parse().unwrap()));
+    assert_eq!(config.table.size, TableConfig::default().size);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].starts_with("SHARED_SERVE_TABLE_SIZE: invalid value `many`"));
+    // Only bad values of known settings are errors
+    assert_eq!(unknown, vec!["SHARED_SERVE_TABLE_COLOR".to_string()]);
+}
+
+#[test]
+fn test_validate_reports_all_errors() {
+    let mut errors = Vec::new();
+    ServerConfig::default().validate(&mut errors);
+    assert_eq!(errors, Vec::<String>::new());
+
+    let mut config = ServerConfig::default();
+    config.segment.name = "a/b".to_string();
+    config.segment.capacity = 1;
+    config.segment.mode = 0o044;
+    config.table.size = 0;
+    config.workers.threads = 0;
+    config.metrics.interval = 0;
+    config.validate(&mut errors);
+    assert_eq!(errors.len(), 6, "{:?}", errors);
+    assert!(errors[0].starts_with("segment.name"));
+    assert!(errors[1].starts_with("segment.capacity 1"));
+    assert!(errors[2].contains("owner read and write"));
+    assert_eq!(errors[3], "table.size must be greater than 0");
+}
@@ -0,0 +1,589 @@
+use crate::segment::{Reply, ReplyStatus, Reservation, Segment, REPLY_SLOTS};
+use crate::wire::{self, Stream};
+use crate::{ClientId, Operation, Request, ScanCursor, ScanPage, Stats, STATS_PAGES};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// How long to sleep between polls of the indices and reply slots.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone)]
+pub enum ClientError {
+    /// The segment could not be opened or mapped, usually because the server isn't running.
+    Connect(nix::Error),
+    /// Every slot of the registration table is taken by a live client.
+    TooManyClients,
+    QueueFull,
+    /// The server's table holds its maximum number of keys and rejected a new one.
+    TableFull,
+    /// The server is shutting down and no longer accepts requests.
+    ShuttingDown,
+    /// No reply slot became free within the lock timeout.
+    NoReplySlot,
+    /// The write lock stayed held by another client for longer than the lock timeout.
+    LockTimeout,
+    /// The server did not answer within the reply timeout.
+    ReplyTimeout,
+    /// The server went away and no new one came up within the retry budget.
+    Disconnected,
+    /// The server sent a reply that could not be parsed.
+    InvalidReply,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Connect(e) => write!(f, "{}: Make sure the server is running", e),
+            ClientError::TooManyClients => write!(f, "Too many clients connected"),
+            ClientError::QueueFull => write!(f, "Queue is full"),
+            ClientError::TableFull => write!(f, "Table is full"),
+            ClientError::ShuttingDown => write!(f, "Server is shutting down"),
+            ClientError::NoReplySlot => write!(f, "No reply slot available"),
+            ClientError::LockTimeout => write!(f, "Timed out waiting for queue lock"),
+            ClientError::ReplyTimeout => write!(f, "Timed out waiting for server reply"),
+            ClientError::Disconnected => write!(f, "Lost connection to the server and could not reconnect"),
+            ClientError::InvalidReply => write!(f, "Invalid reply from server"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// How long to wait for the write lock or a free reply slot.
+    pub lock_timeout: Duration,
+    /// How long to wait for the server to answer a request.
+    pub reply_timeout: Duration,
+    /// How to reattach once the server the client is connected to goes away.
+    pub reconnect: RetryPolicy,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            lock_timeout: Duration::from_secs(5),
+            reply_timeout: Duration::from_secs(5),
+            reconnect: RetryPolicy::default(),
+        }
+    }
+}
+
+/// How often and how patiently a client tries to attach to a restarted server.
+///
+/// The first attempt is made right away, the following ones after a delay
+/// that starts at `initial_backoff` and doubles up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts before giving up with `Disconnected`. 0 disables reconnecting.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Never reconnect: requests keep going to the segment the client first attached to.
+    pub fn never() -> Self {
+        RetryPolicy { max_attempts: 0, ..RetryPolicy::default() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_attempts > 0
+    }
+
+    /// Makes up to `max_attempts` attempts, until one returns `Ok(Some(_))` or
+    /// an error that another attempt won't fix.
+    fn retry<T>(&self, mut attempt: impl FnMut() -> Result<Option<T>, ClientError>) -> Result<T, ClientError> {
+        let mut backoff = self.initial_backoff;
+        for n in 0..self.max_attempts {
+            if n > 0 {
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(self.max_backoff);
+            }
+            if let Some(connected) = attempt()? {
+                return Ok(connected);
+            }
+        }
+        Err(ClientError::Disconnected)
+    }
+
+    fn attach(&self, name: &str) -> Result<Attachment, ClientError> {
+        self.retry(|| match Attachment::open(name) {
+            Ok(attachment) if !attachment.is_retired() => Ok(Some(attachment)),
+            // The old server is still draining, or the new one isn't up yet
+            Ok(_) | Err(ClientError::Connect(_)) => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 20,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// A registration with the segment of one server.
+pub(crate) struct Attachment {
+    pub(crate) segment: Segment,
+    pub(crate) id: ClientId,
+    generation: u64,
+}
+
+impl Attachment {
+    pub(crate) fn open(name: &str) -> Result<Self, ClientError> {
+        let segment = Segment::open(name).map_err(ClientError::Connect)?;
+        let generation = segment.header().generation.load(Ordering::Acquire);
+        let id = segment.register_client().ok_or(ClientError::TooManyClients)?;
+        Ok(Attachment { segment, id, generation })
+    }
+
+    /// Whether requests sent through this attachment would go unanswered:
+    /// the server shut down or died, or a restarted server reset the
+    /// segment and with it our registration.
+    pub(crate) fn is_retired(&self) -> bool {
+        !self.segment.accepts_requests()
+            || self.segment.header().generation.load(Ordering::Acquire) != self.generation
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        // After a reset the registration slot may belong to someone else
+        if self.segment.header().generation.load(Ordering::Acquire) == self.generation {
+            self.segment.unregister_client(self.id);
+        }
+    }
+}
+
+/// The attachment of a client, replaced whenever the server it belongs to goes away.
+pub(crate) struct Connection {
+    name: String,
+    policy: RetryPolicy,
+    attachment: RwLock<Arc<Attachment>>,
+}
+
+impl Connection {
+    pub(crate) fn open(name: &str, policy: RetryPolicy) -> Result<Self, ClientError> {
+        let attachment = Attachment::open(name)?;
+        Ok(Connection { name: name.to_string(), policy, attachment: RwLock::new(Arc::new(attachment)) })
+    }
+
+    pub(crate) fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    pub(crate) fn id(&self) -> ClientId {
+        self.attachment.read().unwrap().id
+    }
+
+    /// The attachment to send the next request through, reattaching first if it was retired.
+    pub(crate) fn current(&self) -> Result<Arc<Attachment>, ClientError> {
+        let attachment = self.attachment.read().unwrap().clone();
+        if !self.policy.is_enabled() || !attachment.is_retired() {
+            return Ok(attachment);
+        }
+        self.reattach(&attachment)
+    }
+
+    /// Replaces the retired attachment, unless another thread already did.
+    pub(crate) fn reattach(&self, retired: &Arc<Attachment>) -> Result<Arc<Attachment>, ClientError> {
+        let mut attachment = self.attachment.write().unwrap();
+        if Arc::ptr_eq(&attachment, retired) {
+            *attachment = Arc::new(self.policy.attach(&self.name)?);
+        }
+        Ok(attachment.clone())
+    }
+}
+
+/// Most requests of a pipeline sent ahead of their replies, so that neither
+/// side blocks on a full socket buffer. As many as the server reads ahead.
+const PIPELINE_DEPTH: usize = wire::MAX_IN_FLIGHT;
+
+/// Where a server listens for socket connections.
+enum Endpoint {
+    Unix(PathBuf),
+    Tcp(Vec<SocketAddr>),
+}
+
+impl Endpoint {
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        match self {
+            Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+            Endpoint::Tcp(addresses) => {
+                let mut last_error = io::Error::from(ErrorKind::AddrNotAvailable);
+                for address in addresses {
+                    match TcpStream::connect_timeout(address, timeout) {
+                        Ok(stream) => {
+                            // Requests are small and answered one by one
+                            stream.set_nodelay(true)?;
+                            return Ok(Box::new(stream));
+                        }
+                        Err(e) => last_error = e,
+                    }
+                }
+                Err(last_error)
+            }
+        }
+    }
+}
+
+/// An open socket to a server and the identity the server gave it.
+struct SocketStream {
+    stream: Box<dyn Stream>,
+    id: ClientId,
+}
+
+impl SocketStream {
+    fn open(endpoint: &Endpoint, config: &ClientConfig) -> Result<Self, ClientError> {
+        let connect_error = |e: io::Error| match e.raw_os_error() {
+            Some(errno) => ClientError::Connect(nix::Error::from_raw(errno)),
+            // The socket is not a server's, or it hung up right away
+            None => ClientError::InvalidReply,
+        };
+        let mut stream = endpoint.connect(config.lock_timeout).map_err(connect_error)?;
+        stream.set_read_timeout(Some(config.reply_timeout)).map_err(connect_error)?;
+        stream.set_write_timeout(Some(config.lock_timeout)).map_err(connect_error)?;
+        let hello = wire::read_frame(&mut stream).map_err(connect_error)?.ok_or(ClientError::InvalidReply)?;
+        if hello.is_empty() {
+            // The server is at its connection limit
+            return Err(ClientError::TooManyClients);
+        }
+        let id = wire::decode_hello(&hello).map_err(|_| ClientError::InvalidReply)?;
+        Ok(SocketStream { stream, id })
+    }
+}
+
+/// A connection to a socket of a server, reopened when the server closes it
+/// and the retry policy allows.
+struct SocketConnection {
+    endpoint: Endpoint,
+    config: ClientConfig,
+    /// Requests of all threads share the socket, one call at a time.
+    stream: Mutex<Option<SocketStream>>,
+}
+
+impl SocketConnection {
+    fn open(endpoint: Endpoint, config: ClientConfig) -> Result<Self, ClientError> {
+        let stream = SocketStream::open(&endpoint, &config)?;
+        Ok(SocketConnection { endpoint, config, stream: Mutex::new(Some(stream)) })
+    }
+
+    fn id(&self) -> ClientId {
+        self.stream.lock().unwrap().as_ref().map_or(ClientId::default(), |stream| stream.id)
+    }
+
+    fn reopen(&self) -> Result<SocketStream, ClientError> {
+        self.config.reconnect.retry(|| match SocketStream::open(&self.endpoint, &self.config) {
+            Ok(stream) => Ok(Some(stream)),
+            // The old server is gone, or the new one isn't listening yet
+            Err(ClientError::Connect(_)) => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Sends the requests with consecutive ids from `first_id` on and returns
+    /// their replies in the same order. Requests are sent ahead of the replies
+    /// to earlier ones, up to `PIPELINE_DEPTH` at a time.
+    fn pipeline(&self, requests: &[Request], first_id: u64) -> Result<Vec<Reply>, ClientError> {
+        let mut guard = self.stream.lock().unwrap();
+        // The server may have closed an idle connection, or gone away since the last call
+        if guard.as_ref().is_some_and(|stream| wire::peer_closed(stream.stream.as_raw_fd())) {
+            *guard = None;
+            if !self.config.reconnect.is_enabled() {
+                return Err(ClientError::Disconnected);
+            }
+        }
+        let stream = match guard.as_mut() {
+            Some(stream) => stream,
+            None => guard.insert(self.reopen()?),
+        };
+
+        let result = Self::exchange(&mut stream.stream, requests, first_id);
+        if matches!(result, Err(ClientError::Disconnected | ClientError::InvalidReply)) {
+            *guard = None;
+        }
+        result
+    }
+
+    fn exchange(stream: &mut Box<dyn Stream>, requests: &[Request], first_id: u64) -> Result<Vec<Reply>, ClientError> {
+        let mut replies: Vec<Option<Reply>> = vec![None; requests.len()];
+        let (mut sent, mut received) = (0, 0);
+        while received < requests.len() {
+            while sent < requests.len() && sent - received < PIPELINE_DEPTH {
+                let mut request = requests[sent];
+                request.request_id = first_id + sent as u64;
+                wire::write_frame(stream, &wire::encode_request(&request)).map_err(|_| ClientError::Disconnected)?;
+                sent += 1;
+            }
+            let reply = match wire::read_frame(stream) {
+                Ok(Some(body)) => wire::decode_reply(&body).map_err(|_| ClientError::InvalidReply)?,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(ClientError::ReplyTimeout)
+                }
+                Ok(None) | Err(_) => return Err(ClientError::Disconnected),
+            };
+            // Skip the late replies to requests that timed out
+            let Some(index) = reply.request_id.checked_sub(first_id) else {
+                continue;
+            };
+            match replies.get_mut(index as usize) {
+                Some(slot @ None) if (index as usize) < sent => {
+                    *slot = Some(reply);
+                    received += 1;
+                }
+                _ => return Err(ClientError::InvalidReply),
+            }
+        }
+        Ok(replies.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+/// How a client reaches its server.
+enum Transport {
+    Segment(Connection),
+    Socket(SocketConnection),
+}
+
+/// A connection to a running server.
+///
+/// If the server shuts down or crashes, the client reattaches to the segment
+/// of the next server with the same name before sending further requests,
+/// following `ClientConfig::reconnect`. Clients connected through a socket
+/// reopen it instead. Requests already waiting for a reply are not resent.
+pub struct Client {
+    transport: Transport,
+    config: ClientConfig,
+    next_request_id: AtomicU64,
+}
+
+impl Client {
+    pub fn connect(name: &str) -> Result<Self, ClientError> {
+        Self::connect_with(name, ClientConfig::default())
+    }
+
+    pub fn connect_with(name: &str, config: ClientConfig) -> Result<Self, ClientError> {
+        let connection = Connection::open(name, config.reconnect.clone())?;
+        Ok(Client { transport: Transport::Segment(connection), config, next_request_id: AtomicU64::new(1) })
+    }
+
+    /// Connects through the Unix domain socket a server listens on with `--socket`,
+    /// for processes that can't map its shared memory. A closed connection is
+    /// reopened following `ClientConfig::reconnect`.
+    pub fn connect_socket(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        Self::connect_socket_with(path, ClientConfig::default())
+    }
+
+    pub fn connect_socket_with(path: impl AsRef<Path>, config: ClientConfig) -> Result<Self, ClientError> {
+        let connection = SocketConnection::open(Endpoint::Unix(path.as_ref().to_path_buf()), config.clone())?;
+        Ok(Client { transport: Transport::Socket(connection), config, next_request_id: AtomicU64::new(1) })
+    }
+
+    /// Connects over TCP to a server listening with `--tcp`, e.g. on another host.
+    /// A closed connection is reopened following `ClientConfig::reconnect`.
+    pub fn connect_tcp(address: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        Self::connect_tcp_with(address, ClientConfig::default())
+    }
+
+    pub fn connect_tcp_with(address: impl ToSocketAddrs, config: ClientConfig) -> Result<Self, ClientError> {
+        let addresses = address
+            .to_socket_addrs()
+            .map_err(|e| ClientError::Connect(nix::Error::from_raw(e.raw_os_error().unwrap_or(nix::libc::EINVAL))))?
+            .collect();
+        let connection = SocketConnection::open(Endpoint::Tcp(addresses), config.clone())?;
+        Ok(Client { transport: Transport::Socket(connection), config, next_request_id: AtomicU64::new(1) })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// The identity the server sees on requests from this client.
+    /// It changes when the client reattaches to a restarted server.
+    pub fn id(&self) -> ClientId {
+        match &self.transport {
+            Transport::Segment(connection) => connection.id(),
+            Transport::Socket(socket) => socket.id(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>, ClientError> {
+        let Reply { status, value, .. } = self.call(Request::new(Operation::GET, key, ""))?;
+        Ok(match status {
+            ReplyStatus::Ok => Some(value),
+            ReplyStatus::NotFound | ReplyStatus::Full => None,
+        })
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        match self.call(Request::new(Operation::INSERT, key, value))?.status {
+            ReplyStatus::Full => Err(ClientError::TableFull),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether the key was present.
+    pub fn delete(&self, key: &str) -> Result<bool, ClientError> {
+        let reply = self.call(Request::new(Operation::DELETE, key, ""))?;
+        Ok(reply.status == ReplyStatus::Ok)
+    }
+
+    /// Returns the server's counters. The server reports them in several pages,
+    /// so counters of different pages may be a few requests apart.
+    pub fn stats(&self) -> Result<Stats, ClientError> {
+        let requests: Vec<Request> = (0..STATS_PAGES).map(|page| Request::new(Operation::STATS, &page.to_string(), "")).collect();
+        let mut pages = Vec::with_capacity(STATS_PAGES);
+        for reply in self.pipeline(&requests)? {
+            if reply.status != ReplyStatus::Ok {
+                return Err(ClientError::InvalidReply);
+            }
+            pages.push(reply.value);
+        }
+        Stats::parse(&pages.join(" ")).ok_or(ClientError::InvalidReply)
+    }
+
+    /// Returns the page of keys starting at `cursor`. Start with `ScanCursor::default()`
+    /// and continue with `ScanPage::next` until it is `None`.
+    pub fn scan(&self, cursor: ScanCursor) -> Result<ScanPage, ClientError> {
+        let reply = self.call(Request::new(Operation::SCAN, &cursor.to_string(), ""))?;
+        match reply.status {
+            ReplyStatus::Ok => ScanPage::parse(&reply.value).ok_or(ClientError::InvalidReply),
+            _ => Err(ClientError::InvalidReply),
+        }
+    }
+
+    /// Returns every key, page by page. This is not a snapshot: keys changed
+    /// meanwhile may be missing or listed twice.
+    pub fn keys(&self) -> Result<Vec<String>, ClientError> {
+        let mut keys = Vec::new();
+        let mut cursor = Some(ScanCursor::default());
+        while let Some(start) = cursor {
+            let page = self.scan(start)?;
+            keys.extend(page.keys);
+            cursor = page.next;
+        }
+        Ok(keys)
+    }
+
+    /// Sends the requests and returns their replies in the same order. Over a
+    /// socket, the requests are sent without waiting for the replies to earlier
+    /// ones, which saves a round trip per request on a remote connection. On the
+    /// shared memory segment they are sent one after another; see `AsyncClient`
+    /// for pipelining there.
+    pub fn pipeline(&self, requests: &[Request]) -> Result<Vec<Reply>, ClientError> {
+        match &self.transport {
+            Transport::Segment(_) => requests.iter().map(|&request| self.call(request)).collect(),
+            Transport::Socket(socket) => {
+                let first_id = self.next_request_id.fetch_add(requests.len() as u64, Ordering::Relaxed);
+                socket.pipeline(requests, first_id)
+            }
+        }
+    }
+
+    /// Enqueues the request and blocks until the server replies.
+    fn call(&self, request: Request) -> Result<Reply, ClientError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let connection = match &self.transport {
+            Transport::Segment(connection) => connection,
+            Transport::Socket(socket) => return Ok(socket.pipeline(&[request], request_id)?.remove(0)),
+        };
+        let mut attachment = connection.current()?;
+        loop {
+            match self.call_on(&attachment, request, request_id) {
+                // The server started shutting down after we last checked
+                Err(ClientError::ShuttingDown) if connection.policy().is_enabled() => {
+                    attachment = connection.reattach(&attachment)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn call_on(&self, attachment: &Attachment, mut request: Request, request_id: u64) -> Result<Reply, ClientError> {
+        let segment = &attachment.segment;
+        let slot_index = self.claim_reply_slot(segment)?;
+        let slot = segment.reply_slot(slot_index).unwrap();
+        request.reply_slot = slot_index as u32;
+        request.stamp(attachment.id, request_id);
+
+        if let Err(e) = self.enqueue(segment, request) {
+            slot.release();
+            return Err(e);
+        }
+
+        let deadline = Instant::now() + self.config.reply_timeout;
+        loop {
+            if let Some(reply) = slot.take() {
+                return Ok(reply);
+            }
+            if Instant::now() >= deadline {
+                // Unless the reply arrived while we were giving up. If the slot was
+                // reset by a restarted server, there is nothing left to wait for.
+                if slot.abandon() || !slot.is_answered() {
+                    return Err(ClientError::ReplyTimeout);
+                }
+                continue;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn claim_reply_slot(&self, segment: &Segment) -> Result<usize, ClientError> {
+        let deadline = Instant::now() + self.config.lock_timeout;
+        loop {
+            if let Some(index) = try_claim_reply_slot(segment) {
+                return Ok(index);
+            }
+            if Instant::now() >= deadline {
+                return Err(ClientError::NoReplySlot);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn enqueue(&self, segment: &Segment, request: Request) -> Result<(), ClientError> {
+        let deadline = Instant::now() + self.config.lock_timeout;
+        loop {
+            if try_enqueue(segment, &request)? {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(ClientError::LockTimeout);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Claims the first free reply slot, if there is one.
+pub(crate) fn try_claim_reply_slot(segment: &Segment) -> Option<usize> {
+    // Start at a per-process offset so concurrent clients don't race for the same slots
+    let start = std::process::id() as usize;
+    (0..REPLY_SLOTS)
+        .map(|i| (start + i) % REPLY_SLOTS)
+        .find(|&index| segment.reply_slot(index).unwrap().try_claim())
+}
+
+/// Makes one attempt at writing the request into the ring.
+/// Returns `Ok(false)` if another client currently holds the write lock.
+pub(crate) fn try_enqueue(segment: &Segment, request: &Request) -> Result<bool, ClientError> {
+    match segment.try_reserve() {
+        Reservation::Reserved(slot) => {
+            slot.commit(request);
+            Ok(true)
+        }
+        Reservation::Full => Err(ClientError::QueueFull),
+        Reservation::Locked => Ok(false),
+        Reservation::Closed => Err(ClientError::ShuttingDown),
+    }
+}
@@ -0,0 +1,328 @@
+use crate::ClientId;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Requests of a client that were dispatched but haven't finished yet.
+struct Session {
+    shard: usize,
+    in_flight: usize,
+    /// Jobs of the session among the held back ones.
+    held: usize,
+}
+
+/// A job that can't be queued on its shard yet.
+struct Held {
+    session: Option<ClientId>,
+    shard: usize,
+    job: Job,
+}
+
+struct Schedule {
+    sessions: HashMap<ClientId, Session>,
+    /// Held back jobs in dispatch order.
+    held: VecDeque<Held>,
+    /// Held back jobs per shard.
+    held_on_shard: Vec<usize>,
+}
+
+impl Schedule {
+    /// Whether a job may be queued on `shard` right away: no earlier job of
+    /// its shard or its session is held back, and its session has no job
+    /// unfinished on another shard.
+    fn can_queue(&self, session: Option<ClientId>, shard: usize) -> bool {
+        self.held_on_shard[shard] == 0
+            && session.and_then(|session| self.sessions.get(&session)).is_none_or(|entry| {
+                entry.held == 0 && (entry.in_flight == 0 || entry.shard == shard)
+            })
+    }
+
+    fn hold(&mut self, session: Option<ClientId>, shard: usize, job: Job) {
+        if let Some(session) = session {
+            self.sessions.entry(session).or_insert(Session { shard, in_flight: 0, held: 0 }).held += 1;
+        }
+        self.held_on_shard[shard] += 1;
+        self.held.push_back(Held { session, shard, job });
+    }
+
+    /// Queues the job, counting it as in flight for its session until it has run.
+    fn queue(&mut self, shared: &Shared, senders: &Arc<Vec<Sender<Job>>>, session: Option<ClientId>, shard: usize, job: Job) {
+        let Some(session) = session else {
+            senders[shard].send(job).expect("Worker thread exited");
+            return;
+        };
+        let entry = self.sessions.entry(session).or_insert(Session { shard, in_flight: 0, held: 0 });
+        entry.shard = shard;
+        entry.in_flight += 1;
+        let guard = SessionGuard { senders: Arc::downgrade(senders), shared: shared.clone(), session };
+        senders[shard]
+            .send(Box::new(move || {
+                let _guard = guard;
+                job();
+            }))
+            .expect("Worker thread exited");
+    }
+
+    /// Queues the held back jobs that may go now, keeping the others in order.
+    fn release(&mut self, shared: &Shared, senders: &Arc<Vec<Sender<Job>>>) {
+        let held = std::mem::take(&mut self.held);
+        self.held_on_shard.fill(0);
+        for entry in self.sessions.values_mut() {
+            entry.held = 0;
+        }
+        for Held { session, shard, job } in held {
+            if self.can_queue(session, shard) {
+                self.queue(shared, senders, session, shard, job);
+            } else {
+                self.hold(session, shard, job);
+            }
+        }
+    }
+}
+
+type Shared = Arc<(Mutex<Schedule>, Condvar)>;
+
+/// Counts a job of a session as finished when dropped, which also happens
+/// while unwinding from a job that panicked. The last job in flight of a
+/// session lets the jobs held back behind it go.
+struct SessionGuard {
+    senders: Weak<Vec<Sender<Job>>>,
+    shared: Shared,
+    session: ClientId,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let (schedule, idle) = &*self.shared;
+        let mut schedule = schedule.lock().unwrap();
+        let entry = schedule.sessions.get_mut(&self.session).unwrap();
+        entry.in_flight -= 1;
+        if entry.in_flight > 0 {
+            return;
+        }
+        if let Some(senders) = self.senders.upgrade() {
+            schedule.release(&self.shared, &senders);
+        }
+        schedule.sessions.retain(|_, entry| entry.in_flight > 0 || entry.held > 0);
+        // Wakes up `join` waiting for the held back jobs
+        idle.notify_all();
+    }
+}
+
+/// A pool of workers where each worker drains its own queue.
+///
+/// Jobs dispatched to the same shard run one after another in dispatch order,
+/// while jobs on different shards run in parallel. The server routes requests
+/// by the bucket of their key, so operations on one key are applied in the
+/// order they were dequeued.
+///
+/// Jobs of one client session are also applied in dispatch order: a job for
+/// a different shard than the session's unfinished jobs is held back and
+/// queued once those are done, so dispatching never waits. Later jobs of
+/// the same shard, from any session, are held back behind it to keep the
+/// order of operations on one key.
+///
+/// A job that panics is logged and skipped, so its worker keeps serving
+/// the shard.
+pub struct Dispatcher {
+    senders: Arc<Vec<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+    shared: Shared,
+}
+
+impl Dispatcher {
+    pub fn new(num_shards: usize) -> Self {
+        assert!(num_shards > 0, "Dispatcher needs at least one shard");
+        let mut senders = Vec::with_capacity(num_shards);
+        let mut workers = Vec::with_capacity(num_shards);
+        for shard in 0..num_shards {
+            let (sender, receiver) = channel::<Job>();
+            senders.push(sender);
+            workers.push(
+                thread::Builder::new()
+                    .name(format!("worker-{}", shard))
+                    .spawn(move || {
+                        for job in receiver {
+                            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                log::error!(shard; "job panicked, continuing with the next one");
+                            }
+                        }
+                    })
+                    .expect("Failed to spawn worker thread"),
+            );
+        }
+        let schedule = Schedule { sessions: HashMap::new(), held: VecDeque::new(), held_on_shard: vec![0; num_shards] };
+        Dispatcher { senders: Arc::new(senders), workers, shared: Arc::new((Mutex::new(schedule), Condvar::new())) }
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Queues the job of `session` on the shard responsible for `bucket`.
+    /// Requests without a client identity (pid 0) aren't ordered by session.
+    pub fn execute<F>(&self, session: ClientId, bucket: usize, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shard = bucket % self.senders.len();
+        let session = (session.pid != 0).then_some(session);
+        let mut schedule = self.shared.0.lock().unwrap();
+        if schedule.can_queue(session, shard) {
+            schedule.queue(&self.shared, &self.senders, session, shard, Box::new(job));
+        } else {
+            schedule.hold(session, shard, Box::new(job));
+        }
+    }
+
+    /// Waits for all queued jobs to finish and stops the workers.
+    pub fn join(self) {
+        // Held back jobs are queued by the last job in flight of the session
+        // they wait for, which needs the senders
+        let (schedule, idle) = &*self.shared;
+        let mut schedule = schedule.lock().unwrap();
+        while !schedule.held.is_empty() {
+            schedule = idle.wait(schedule).unwrap();
+        }
+        drop(schedule);
+        drop(self.senders);
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Unit tests for the dispatcher
+#[test]
+fn test_dispatcher_keeps_shard_order() {
+    let dispatcher = Dispatcher::new(4);
+    let applied = Arc::new(Mutex::new(vec![Vec::new(); 8]));
+    for i in 0..1000 {
+        let bucket = i % 8;
+        let applied = applied.clone();
+        dispatcher.execute(ClientId::default(), bucket, move || applied.lock().unwrap()[bucket].push(i));
+    }
+    dispatcher.join();
+
+    for (bucket, sequence) in applied.lock().unwrap().iter().enumerate() {
+        let expected: Vec<usize> = (0..1000).filter(|i| i % 8 == bucket).collect();
+        assert_eq!(*sequence, expected);
+    }
+}
+
+#[test]
+fn test_dispatcher_join_runs_queued_jobs() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let dispatcher = Dispatcher::new(2);
+    let count = Arc::new(AtomicUsize::new(0));
+    for i in 0..100 {
+        let count = count.clone();
+        dispatcher.execute(ClientId::default(), i, move || {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    dispatcher.join();
+    assert_eq!(count.load(Ordering::Relaxed), 100);
+}
+
+#[test]
+fn test_dispatcher_keeps_session_order() {
+    use std::time::Duration;
+
+    let dispatcher = Dispatcher::new(4);
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let session = ClientId { pid: 1, slot: 0 };
+    for i in 0..50 {
+        let applied = applied.clone();
+        // Make the jobs on shard 0 slow, so later jobs on other shards would overtake them
+        dispatcher.execute(session, i, move || {
+            if i % 4 == 0 {
+                thread::sleep(Duration::from_millis(2));
+            }
+            applied.lock().unwrap().push(i);
+        });
+    }
+    dispatcher.join();
+    assert_eq!(*applied.lock().unwrap(), (0..50).collect::<Vec<usize>>());
+}
+
+#[test]
+fn test_dispatcher_survives_panicking_job() {
+    // Job 2 goes to another shard than job 1, so it waits for the session's
+    // panicking job to be counted as finished
+    let dispatcher = Dispatcher::new(2);
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let session = ClientId { pid: 1, slot: 0 };
+    for i in 0..4 {
+        let applied = applied.clone();
+        dispatcher.execute(session, i, move || {
+            if i == 1 {
+                panic!("job {} failed", i);
+            }
+            applied.lock().unwrap().push(i);
+        });
+    }
+    dispatcher.join();
+    assert_eq!(*applied.lock().unwrap(), vec![0, 2, 3]);
+}
+
+#[test]
+fn test_dispatcher_holds_back_session_jobs_without_blocking() {
+    use std::sync::mpsc;
+
+    let dispatcher = Dispatcher::new(4);
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let session = ClientId { pid: 1, slot: 0 };
+    let (release, blocked) = mpsc::channel::<()>();
+    let first = applied.clone();
+    dispatcher.execute(session, 0, move || {
+        blocked.recv().unwrap();
+        first.lock().unwrap().push(0);
+    });
+    // Returns while the session's job on shard 0 still runs
+    for i in 1..6 {
+        let applied = applied.clone();
+        dispatcher.execute(session, 1 + i % 2, move || applied.lock().unwrap().push(i));
+    }
+    // Other sessions on shards without held back jobs aren't held up
+    let other = applied.clone();
+    dispatcher.execute(ClientId { pid: 2, slot: 0 }, 3, move || other.lock().unwrap().push(100));
+    while !applied.lock().unwrap().contains(&100) {
+        thread::yield_now();
+    }
+
+    release.send(()).unwrap();
+    dispatcher.join();
+    assert_eq!(*applied.lock().unwrap(), vec![100, 0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_dispatcher_holds_back_other_sessions_on_same_shard() {
+    use std::sync::mpsc;
+
+    let dispatcher = Dispatcher::new(2);
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let (release, blocked) = mpsc::channel::<()>();
+    let first = applied.clone();
+    dispatcher.execute(ClientId { pid: 1, slot: 0 }, 0, move || {
+        blocked.recv().unwrap();
+        first.lock().unwrap().push("a0");
+    });
+    // Held back behind the session's job on shard 0
+    let second = applied.clone();
+    dispatcher.execute(ClientId { pid: 1, slot: 0 }, 1, move || second.lock().unwrap().push("a1"));
+    // Later jobs on shard 1 wait for it, whatever their session
+    let other = applied.clone();
+    dispatcher.execute(ClientId { pid: 2, slot: 0 }, 1, move || other.lock().unwrap().push("b1"));
+    let anonymous = applied.clone();
+    dispatcher.execute(ClientId::default(), 1, move || anonymous.lock().unwrap().push("c1"));
+
+    release.send(()).unwrap();
+    dispatcher.join();
+    assert_eq!(*applied.lock().unwrap(), vec!["a0", "a1", "b1", "c1"]);
+}
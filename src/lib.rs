@@ -1,25 +1,35 @@
 #![allow(dead_code)]
 use std::collections::LinkedList;
 use std::sync::{RwLock, Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[repr(C)]
-pub struct Header {
-    pub read_index: RwLock<usize>,
-    pub write_index: RwLock<usize>,
-}
+pub mod admin;
+pub mod command;
+pub mod async_client;
+pub mod config;
+pub mod connection;
+pub mod dispatcher;
+pub mod logging;
+pub mod metrics;
+pub mod scan;
+pub mod segment;
+pub mod stats;
+pub mod wire;
+pub mod workload;
 
-impl Header {
-    pub fn new() -> Self {
-        Header {
-            read_index: RwLock::new(0),
-            write_index: RwLock::new(0),
-        }
-    }
-}
+pub use async_client::AsyncClient;
+pub use config::ServerConfig;
+pub use connection::{Client, ClientConfig, ClientError, RetryPolicy};
+pub use dispatcher::Dispatcher;
+pub use scan::{ScanCursor, ScanPage};
+pub use stats::{Stats, STATS_PAGES};
+pub use segment::{Dequeued, Header, Reply, ReplyStatus, Reservation, Segment, SegmentError, Startup, DEFAULT_CAPACITY, DEFAULT_SEGMENT_MODE, DEFAULT_SEGMENT_NAME, MAX_CAPACITY, MAX_CLIENTS, REPLY_SLOTS};
 
-pub const CAPACITY: usize = 10;
-pub const SHARED_MEMORY_SIZE: usize = std::mem::size_of::<Header>() + std::mem::size_of::<Request>() * CAPACITY;
+/// `Request::reply_slot` value for requests that don't expect a reply.
+pub const NO_REPLY: u32 = u32::MAX;
 
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -27,6 +37,24 @@ pub enum Operation {
     GET = 0,
     INSERT = 1,
     DELETE = 2,
+    /// Asks for the server's counters, see `Stats`.
+    STATS = 3,
+    /// Asks for a page of keys starting at the cursor in the key, see `ScanPage`.
+    SCAN = 4,
+}
+
+/// Identifies a connected client by its pid and registration slot in the segment.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct ClientId {
+    pub pid: u32,
+    pub slot: u32,
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.pid, self.slot)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -34,7 +62,11 @@ pub enum Operation {
 pub struct Request {
     pub operation: Operation,
     pub key: [u8; 64],     // Fixed buffer for key
-    pub value: [u8; 256], 
+    pub value: [u8; 256],
+    pub reply_slot: u32,   // Index of the reply slot, or NO_REPLY
+    pub client: ClientId,
+    pub request_id: u64,   // Increases with every request of a client
+    pub timestamp_us: u64, // Microseconds since the UNIX epoch at submission
 }
 
 impl Request {
@@ -49,9 +81,23 @@ impl Request {
             operation,
             key: key_buffer,
             value: value_buffer,
+            reply_slot: NO_REPLY,
+            client: ClientId::default(),
+            request_id: 0,
+            timestamp_us: 0,
         }
     }
 
+    /// Tags the request with the sender's identity and the current time.
+    pub fn stamp(&mut self, client: ClientId, request_id: u64) {
+        self.client = client;
+        self.request_id = request_id;
+        self.timestamp_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+    }
+
     /// Returns the key as a &str, excluding any trailing null bytes.
     pub fn key_str(&self) -> &str {
         // Only convert first 64 bytes of the key
@@ -65,7 +111,7 @@ impl Request {
     }
 
     /// Helper function to convert &[u8] to &str by finding the first \0
-    fn bytes_to_str(bytes: &[u8]) -> &str {
+    pub(crate) fn bytes_to_str(bytes: &[u8]) -> &str {
         if let Some(pos) = bytes.iter().position(|&c| c == 0) {
             // Safe to unwrap because we're slicing at a valid UTF-8 boundary
             std::str::from_utf8(&bytes[..pos]).unwrap_or("<invalid utf8>")
@@ -80,7 +126,9 @@ impl fmt::Display for Request {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Operation: {:?}, Key: {}, Value: {}",
+            "Client: {}, Request ID: {}, Operation: {:?}, Key: {}, Value: {}",
+            self.client,
+            self.request_id,
             self.operation,
             self.key_str(),
             self.value_str()
@@ -94,13 +142,47 @@ struct HashCell {
     value: String,
 }
 
+/// What an INSERT of a new key does once the table holds `max_keys` keys.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Eviction {
+    /// Refuse the new key, the client gets `ReplyStatus::Full`.
+    #[default]
+    Reject,
+    /// Drop the oldest key of the new key's bucket, or of another bucket if that one is empty.
+    EvictOldest,
+}
+
+impl FromStr for Eviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Eviction::Reject),
+            "evict-oldest" => Ok(Eviction::EvictOldest),
+            _ => Err(format!("unknown eviction policy `{}`, expected `reject` or `evict-oldest`", s)),
+        }
+    }
+}
+
 pub struct HashTable {
     buckets: Vec<Arc<RwLock<LinkedList<HashCell>>>>,
     size: usize,
+    len: AtomicUsize,
+    bytes: AtomicUsize,
+    /// Most keys the table holds, 0 for no limit.
+    max_keys: usize,
+    eviction: Eviction,
 }
 
 impl HashTable {
     pub fn new(size: usize) -> Self {
+        Self::with_limit(size, 0, Eviction::Reject)
+    }
+
+    /// A table that holds at most `max_keys` keys and makes room for new ones
+    /// according to `eviction`. A `max_keys` of 0 means no limit.
+    pub fn with_limit(size: usize, max_keys: usize, eviction: Eviction) -> Self {
         let mut buckets = Vec::with_capacity(size);
         for _ in 0..size {
             buckets.push(Arc::new(RwLock::new(LinkedList::new())));
@@ -108,6 +190,10 @@ impl HashTable {
         HashTable {
             buckets,
             size,
+            len: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            max_keys,
+            eviction,
         }
     }
 
@@ -123,21 +209,72 @@ impl HashTable {
         self.hash(key)
     }
 
-    pub fn insert(&self, key: &str, value: &str) {
-        let index = self.get_bucket(&key);
+    /// Returns false if the key is new and the table is full.
+    pub fn insert(&self, key: &str, value: &str) -> bool {
+        let index = self.get_bucket(key);
         let mut bucket = self.buckets[index].write().unwrap();
         
         for cell in bucket.iter_mut() {
             if cell.key == key {
+                self.bytes.fetch_add(value.len(), Ordering::Relaxed);
+                self.bytes.fetch_sub(cell.value.len(), Ordering::Relaxed);
                 cell.value = value.to_string();
-                return;
+                return true;
+            }
+        }
+        if !self.reserve_key() {
+            match self.eviction {
+                Eviction::Reject => return false,
+                Eviction::EvictOldest => {
+                    // New keys are appended, so the front of a chain is its oldest key.
+                    // The new key takes the evicted one's place in the count.
+                    let evicted = match bucket.pop_front() {
+                        Some(cell) => Some(cell),
+                        None => self.evict_elsewhere(index),
+                    };
+                    match evicted {
+                        Some(cell) => {
+                            self.bytes.fetch_sub(cell.key.len() + cell.value.len(), Ordering::Relaxed);
+                        },
+                        None => return false,
+                    }
+                },
             }
         }
         bucket.push_back(HashCell { key: key.to_string(), value: value.to_string() });
+        self.bytes.fetch_add(key.len() + value.len(), Ordering::Relaxed);
+        true
+    }
+
+    /// Counts a new key unless the table is full. Inserts into different
+    /// buckets don't share a lock, so the count is claimed before the key is
+    /// added to keep them from together going past `max_keys`.
+    fn reserve_key(&self) -> bool {
+        if self.max_keys == 0 {
+            self.len.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        let mut len = self.len.load(Ordering::Relaxed);
+        while len < self.max_keys {
+            match self.len.compare_exchange_weak(len, len + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => len = current,
+            }
+        }
+        false
+    }
+
+    /// Pops the oldest key of the first non-empty bucket after `index`.
+    /// Buckets locked by other workers are skipped, since waiting for them
+    /// while holding the lock of `index` could deadlock.
+    fn evict_elsewhere(&self, index: usize) -> Option<HashCell> {
+        (1..self.size)
+            .map(|offset| (index + offset) % self.size)
+            .find_map(|other| self.buckets[other].try_write().ok()?.pop_front())
     }
 
     pub fn get(&self, key: &str) -> Option<String> {
-        let index = self.get_bucket(&key);
+        let index = self.get_bucket(key);
         // Get a read lock on the bucket
         let bucket = self.buckets[index].read().unwrap();
         for cell in bucket.iter() {
@@ -151,11 +288,10 @@ impl HashTable {
     }
 
     pub fn delete(&self, key: &str)-> bool {
-        let index = self.get_bucket(&key);
+        let index = self.get_bucket(key);
         // get position of the cell
         let mut bucket = self.buckets[index].write().unwrap();
         for (position, cell) in bucket.iter().enumerate() {
-            println!("cell: {:?}", cell);
             if cell.key == key {
                 // As remove is not stable and is O(n), 
                 // instead the list is split at the position 
@@ -163,7 +299,10 @@ impl HashTable {
                 // This results in identical complexity.
 
                 let mut tail = bucket.split_off(position);
-                tail.pop_front();
+                if let Some(cell) = tail.pop_front() {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    self.bytes.fetch_sub(cell.key.len() + cell.value.len(), Ordering::Relaxed);
+                }
                 bucket.append(&mut tail);
                 return true;
             }
@@ -171,6 +310,43 @@ impl HashTable {
         false
     }
 
+    /// Number of keys stored.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken up by the stored keys and values.
+    pub fn memory_usage(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Calls `visit` with each key from `cursor` on, bucket by bucket, until it
+    /// returns false. Returns the cursor of the first key not visited, or `None`
+    /// once every key was visited. Keys inserted or deleted in between pages
+    /// may be skipped or returned twice.
+    pub fn scan(&self, cursor: ScanCursor, mut visit: impl FnMut(&str) -> bool) -> Option<ScanCursor> {
+        let mut position = cursor.position;
+        for bucket in cursor.bucket..self.size {
+            let chain = self.buckets[bucket].read().unwrap();
+            for cell in chain.iter().skip(position) {
+                if !visit(&cell.key) {
+                    return Some(ScanCursor { bucket, position });
+                }
+                position += 1;
+            }
+            position = 0;
+        }
+        None
+    }
+
+    /// Number of keys in each bucket.
+    pub fn chain_lengths(&self) -> Vec<usize> {
+        self.buckets.iter().map(|bucket| bucket.read().unwrap().len()).collect()
+    }
 }
 
 // Unit tests for the hash table
@@ -192,6 +368,68 @@ fn test_hash_table_delete() {
     assert_eq!(hash_table.get("key1"), None);
 }
 
+#[test]
+fn test_hash_table_counters() {
+    let hash_table = HashTable::new(10);
+    hash_table.insert("key1", "value1");
+    hash_table.insert("key2", "value2");
+    hash_table.insert("key1", "v");
+    assert_eq!(hash_table.len(), 2);
+    assert_eq!(hash_table.memory_usage(), "key1v".len() + "key2value2".len());
+    hash_table.delete("key2");
+    assert_eq!(hash_table.len(), 1);
+    assert_eq!(hash_table.memory_usage(), "key1v".len());
+    assert_eq!(hash_table.chain_lengths().iter().sum::<usize>(), 1);
+}
+
+#[test]
+fn test_hash_table_reject_when_full() {
+    let hash_table = HashTable::with_limit(10, 2, Eviction::Reject);
+    assert!(hash_table.insert("key1", "value1"));
+    assert!(hash_table.insert("key2", "value2"));
+    assert!(!hash_table.insert("key3", "value3"));
+    // Updating a stored key needs no room
+    assert!(hash_table.insert("key1", "value4"));
+    assert_eq!(hash_table.get("key3"), None);
+    assert_eq!(hash_table.len(), 2);
+}
+
+#[test]
+fn test_hash_table_limit_under_concurrent_inserts() {
+    let hash_table = Arc::new(HashTable::with_limit(16, 50, Eviction::Reject));
+    let handles: Vec<_> = (0..8)
+        .map(|thread| {
+            let table = Arc::clone(&hash_table);
+            std::thread::spawn(move || (0..100).filter(|i| table.insert(&format!("key{}_{}", thread, i), "value")).count())
+        })
+        .collect();
+    let inserted: usize = handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+    assert_eq!(inserted, 50);
+    assert_eq!(hash_table.len(), 50);
+    assert_eq!(hash_table.chain_lengths().iter().sum::<usize>(), 50);
+}
+
+#[test]
+fn test_hash_table_evict_oldest() {
+    // A single bucket, so the eviction order is the insertion order
+    let hash_table = HashTable::with_limit(1, 2, Eviction::EvictOldest);
+    hash_table.insert("key1", "value1");
+    hash_table.insert("key2", "value2");
+    assert!(hash_table.insert("key3", "value3"));
+    assert_eq!(hash_table.get("key1"), None);
+    assert_eq!(hash_table.get("key2").as_deref(), Some("value2"));
+    assert_eq!(hash_table.get("key3").as_deref(), Some("value3"));
+    assert_eq!(hash_table.len(), 2);
+    assert_eq!(hash_table.memory_usage(), "key2value2key3value3".len());
+
+    // Falls back to another bucket when the key's own bucket is empty
+    let hash_table = HashTable::with_limit(10, 1, Eviction::EvictOldest);
+    hash_table.insert("a", "1");
+    assert!(hash_table.insert("b", "2"));
+    assert_eq!(hash_table.get("a"), None);
+    assert_eq!(hash_table.len(), 1);
+}
+
 #[test]
 fn test_hash_table_insert() {
     let hash_table = HashTable::new(10);
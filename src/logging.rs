@@ -0,0 +1,224 @@
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How the logger formats each record.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `<time> <LEVEL> <target>: <message> key=value ...`
+    #[default]
+    Text,
+    /// One JSON object per line with `time`, `level`, `target` and `message`
+    /// next to the key-value pairs of the record.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`, expected `text` or `json`", s)),
+        }
+    }
+}
+
+/// Writes records at or above a level to stderr, one line each.
+///
+/// The library itself only emits records through the `log` macros, so nothing
+/// is printed unless a program installs a logger like this one.
+pub struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(level: LevelFilter, format: LogFormat) -> Self {
+        Logger { level, format }
+    }
+
+    /// Installs the logger for the whole process.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    pub fn format(&self, record: &Record<'_>) -> String {
+        let time = format_time(SystemTime::now());
+        match self.format {
+            LogFormat::Text => format_text(&time, record),
+            LogFormat::Json => format_json(&time, record),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = self.format(record);
+        line.push('\n');
+        // A single write keeps lines of concurrent workers from interleaving
+        let _ = std::io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// A formatted value of a record, and whether it is a number or a boolean.
+struct Field {
+    key: String,
+    value: String,
+    is_primitive: bool,
+}
+
+/// Collects the key-value pairs of a record.
+struct Fields(Vec<Field>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let is_primitive = value.to_u64().is_some()
+            || value.to_i64().is_some()
+            || value.to_f64().is_some_and(f64::is_finite)
+            || value.to_bool().is_some();
+        self.0.push(Field { key: key.to_string(), value: value.to_string(), is_primitive });
+        Ok(())
+    }
+}
+
+fn fields(record: &Record<'_>) -> Vec<Field> {
+    let mut fields = Fields(Vec::new());
+    let _ = record.key_values().visit(&mut fields);
+    fields.0
+}
+
+fn format_text(time: &str, record: &Record<'_>) -> String {
+    let mut line = format!("{} {:<5} {}: {}", time, record.level(), record.target(), record.args());
+    for Field { key, value, .. } in fields(record) {
+        // Quote values that would otherwise be ambiguous to split on
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            let _ = write!(line, " {}={:?}", key, value);
+        } else {
+            let _ = write!(line, " {}={}", key, value);
+        }
+    }
+    line
+}
+
+fn format_json(time: &str, record: &Record<'_>) -> String {
+    let mut line = String::from("{");
+    let _ = write!(line, "\"time\":{}", json_string(time));
+    let _ = write!(line, ",\"level\":{}", json_string(record.level().as_str()));
+    let _ = write!(line, ",\"target\":{}", json_string(record.target()));
+    let _ = write!(line, ",\"message\":{}", json_string(&record.args().to_string()));
+    for Field { key, value, is_primitive } in fields(record) {
+        let value = if is_primitive { value } else { json_string(&value) };
+        let _ = write!(line, ",{}:{}", json_string(&key), value);
+    }
+    line.push('}');
+    line
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Formats the time as an RFC 3339 UTC timestamp with millisecond precision.
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Unit tests for the log formats
+#[test]
+fn test_format_text() {
+    let fields = [("client", "42:1"), ("key", "two words")];
+    let record = Record::builder()
+        .args(format_args!("processed request"))
+        .level(log::Level::Debug)
+        .target("server")
+        .key_values(&fields)
+        .build();
+    assert_eq!(
+        format_text("2024-01-01T00:00:00.000Z", &record),
+        "2024-01-01T00:00:00.000Z DEBUG server: processed request client=42:1 key=\"two words\""
+    );
+}
+
+#[test]
+fn test_format_json() {
+    let fields: [(&str, &dyn kv::ToValue); 2] = [("key", &"quote\"d"), ("pending", &3)];
+    let record = Record::builder()
+        .args(format_args!("line\nbreak"))
+        .level(log::Level::Info)
+        .target("server")
+        .key_values(&fields)
+        .build();
+    assert_eq!(
+        format_json("2024-01-01T00:00:00.000Z", &record),
+        r#"{"time":"2024-01-01T00:00:00.000Z","level":"INFO","target":"server","message":"line\nbreak","key":"quote\"d","pending":3}"#
+    );
+}
+
+#[test]
+fn test_format_time() {
+    use std::time::Duration;
+
+    assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    let leap_day = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+    assert_eq!(format_time(leap_day), "2024-02-29T12:34:56.789Z");
+}
@@ -1,190 +1,598 @@
-use shared_serve::{HashTable, Operation, Request, Header, SHARED_MEMORY_SIZE, CAPACITY};
-use clap::Parser;
-use nix::sys::{mman, mman::ProtFlags, mman::MapFlags};
-use nix::fcntl:: OFlag;
-use nix::unistd::ftruncate;
-use nix::sys::stat::Mode;
+use shared_serve::config::{MetricsConfig, ServerConfig, SocketConfig, TcpConfig};
+use shared_serve::logging::{LogFormat, Logger};
+use shared_serve::metrics::{self, LatencyHistogram, OPERATIONS};
+use shared_serve::wire::{self, ConnectionLimits, Responder};
+use shared_serve::{Dequeued, Dispatcher, HashTable, Operation, Reply, Request, ReplyStatus, ScanPage, Segment, Startup, Stats};
+use clap::{Parser, ValueEnum};
+use log::{debug, error, info, warn, LevelFilter};
 use std::error::Error;
-use nix::libc::off_t;
-use std::num::NonZero;
-use std::os::fd::AsFd;
-use std::ptr;
-use threadpool::ThreadPool;
-use std::sync::{Arc, mpsc::channel};
-use ctrlc;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use nix::libc::c_int;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 
+/// Flags override the config file and the `SHARED_SERVE_*` environment variables.
 #[derive(Parser)]
 struct Args {
-    #[arg(short, long, default_value = "10")]
-    size: usize,
-    #[arg(short, long, default_value = "4")]
-    num_threads: usize,
-}
-
-pub fn setup_shared_memory_server() -> Result<*mut u8, Box<dyn Error>> {
-    // Create the shared memory object
-    let shm_fd = mman::shm_open(
-        "RequestQueue", 
-        OFlag::O_CREAT | OFlag::O_RDWR , 
-        Mode::S_IRUSR | Mode::S_IWUSR)?;
-    
-    ftruncate(
-        shm_fd.as_fd(), 
-        SHARED_MEMORY_SIZE as off_t)?;
-    
-    let ptr = unsafe { 
-        mman::mmap(
-            None, 
-            NonZero::new(SHARED_MEMORY_SIZE).unwrap(), 
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, 
-            MapFlags::MAP_SHARED, 
-            shm_fd, 
-            0)? 
+    /// TOML file with the server settings
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Number of buckets of the hash table [default: 10]
+    #[arg(short, long)]
+    size: Option<usize>,
+    /// Number of worker threads [default: 4]
+    #[arg(short, long)]
+    num_threads: Option<usize>,
+    /// Name of the shared memory segment [default: RequestQueue]
+    #[arg(long)]
+    name: Option<String>,
+    /// Keep the requests still queued in a segment left behind by a crashed server
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    recover: Option<bool>,
+    /// Leave the segment and its pending requests in place on shutdown for the next server
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    preserve_queue: Option<bool>,
+    /// Seconds to keep processing pending requests after a shutdown signal [default: 5]
+    #[arg(long)]
+    drain_timeout: Option<u64>,
+    /// Serve Prometheus metrics on http://127.0.0.1:<port>/metrics
+    #[arg(long)]
+    metrics_port: Option<u16>,
+    /// Periodically write Prometheus metrics to this file, e.g. for the node exporter's textfile collector
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+    /// Seconds between writes of the metrics file [default: 10]
+    #[arg(long)]
+    metrics_interval: Option<u64>,
+    /// Also accept requests on a Unix domain socket at this path
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Also accept requests over TCP on this address, e.g. 0.0.0.0:7878
+    #[arg(long)]
+    tcp: Option<SocketAddr>,
+    /// Most TCP connections open at once, 0 for no limit [default: 64]
+    #[arg(long)]
+    max_connections: Option<usize>,
+    /// Seconds after which a TCP connection that sent no request is closed, 0 to keep it open [default: 300]
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+    /// Least severe level to log: off, error, warn, info, debug or trace [default: info]
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+    /// Format of the log lines written to stderr [default: text]
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormatArg>,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum LogFormatArg {
+    Text,
+    Json,
+}
+
+impl From<LogFormatArg> for LogFormat {
+    fn from(format: LogFormatArg) -> Self {
+        match format {
+            LogFormatArg::Text => LogFormat::Text,
+            LogFormatArg::Json => LogFormat::Json,
+        }
+    }
+}
+
+/// Combines the defaults, the config file, the environment and the flags,
+/// in increasing order of precedence, and validates the result. Next to the
+/// config come the unknown `SHARED_SERVE_*` variables, to be warned about.
+fn load_config(args: &Args) -> Result<(ServerConfig, Vec<String>), Vec<String>> {
+    let mut errors = Vec::new();
+    let mut config = match &args.config {
+        Some(path) => ServerConfig::from_file(path, &mut errors),
+        None => ServerConfig::default(),
     };
+    let unknown_vars = config.apply_env(std::env::vars(), &mut errors);
 
-    Ok(ptr.as_ptr() as *mut u8)
+    if let Some(size) = args.size {
+        config.table.size = size;
+    }
+    if let Some(threads) = args.num_threads {
+        config.workers.threads = threads;
+    }
+    if let Some(name) = &args.name {
+        config.segment.name = name.clone();
+    }
+    if let Some(recover) = args.recover {
+        config.persistence.recover = recover;
+    }
+    if let Some(preserve_queue) = args.preserve_queue {
+        config.persistence.preserve_queue = preserve_queue;
+    }
+    if let Some(drain_timeout) = args.drain_timeout {
+        config.persistence.drain_timeout = drain_timeout;
+    }
+    if args.metrics_port.is_some() {
+        config.metrics.port = args.metrics_port;
+    }
+    if args.metrics_file.is_some() {
+        config.metrics.file = args.metrics_file.clone();
+    }
+    if let Some(interval) = args.metrics_interval {
+        config.metrics.interval = interval;
+    }
+    if args.socket.is_some() {
+        config.socket.path = args.socket.clone();
+    }
+    if args.tcp.is_some() {
+        config.tcp.address = args.tcp;
+    }
+    if let Some(max_connections) = args.max_connections {
+        config.tcp.max_connections = max_connections;
+    }
+    if let Some(idle_timeout) = args.idle_timeout {
+        config.tcp.idle_timeout = idle_timeout;
+    }
+    if let Some(level) = args.log_level {
+        config.logging.level = level;
+    }
+    if let Some(format) = args.log_format {
+        config.logging.format = format.into();
+    }
+
+    config.validate(&mut errors);
+    if errors.is_empty() {
+        Ok((config, unknown_vars))
+    } else {
+        Err(errors)
+    }
 }
 
+/// Number of SIGINTs received. The first one drains the queue, a second one exits immediately.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+/// Set by SIGTERM and SIGHUP, which always drain the queue.
+static TERMINATE: AtomicBool = AtomicBool::new(false);
 
-pub fn get_request(ptr: *mut u8) -> Result<Request, Box<dyn Error>> {
-    unsafe {
-        let header_ptr = ptr as *mut Header;
-        let header = &mut *header_ptr;
-        loop {
-            
-            // Try to acquire read lock
-            let write_index_result = header.write_index.try_read();
-            if write_index_result.is_err() {
-                continue;
-            }
-            let write_guard = write_index_result.unwrap();
-            let write_index = *write_guard;
+extern "C" fn handle_signal(signal: c_int) {
+    if signal == Signal::SIGINT as c_int {
+        INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+    } else {
+        TERMINATE.store(true, Ordering::SeqCst);
+    }
+}
 
-            // Try to acquire write lock
-            let read_index_result = header.read_index.try_write();
-            if read_index_result.is_err() {
-                continue;
-            }
-            let mut read_index_guard = read_index_result.unwrap();
-            let read_index = *read_index_guard;
-           
-            if read_index == write_index {
-                return Err("Server: Queue is empty".into());
-            }
+fn install_signal_handlers() -> nix::Result<()> {
+    let action = SigAction::new(SigHandler::Handler(handle_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        unsafe { sigaction(signal, &action)? };
+    }
+    Ok(())
+}
+
+fn shutdown_requested() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 0 || TERMINATE.load(Ordering::SeqCst)
+}
+
+fn immediate_exit_requested() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 1
+}
+
+/// Counters behind the STATS operation, updated by the workers.
+struct ServerStats {
+    started: Instant,
+    workers: usize,
+    /// Requests processed, indexed by `Operation`.
+    requests: [AtomicU64; OPERATIONS.len()],
+    hits: AtomicU64,
+    misses: AtomicU64,
+    busy_workers: AtomicUsize,
+    busy_us: AtomicU64,
+    /// Latency of each request from submission until its reply, indexed by `Operation`.
+    latencies: [LatencyHistogram; OPERATIONS.len()],
+}
+
+impl ServerStats {
+    fn new(workers: usize) -> Self {
+        ServerStats {
+            started: Instant::now(),
+            workers,
+            requests: Default::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            busy_workers: AtomicUsize::new(0),
+            busy_us: AtomicU64::new(0),
+            latencies: Default::default(),
+        }
+    }
+
+    fn snapshot(&self, hash_table: &HashTable, segment: &Segment) -> Stats {
+        let header = segment.header();
+        let available_us = self.started.elapsed().as_micros() as f64 * self.workers as f64;
+        Stats {
+            gets: self.requests[Operation::GET as usize].load(Ordering::Relaxed),
+            inserts: self.requests[Operation::INSERT as usize].load(Ordering::Relaxed),
+            deletes: self.requests[Operation::DELETE as usize].load(Ordering::Relaxed),
+            stats: self.requests[Operation::STATS as usize].load(Ordering::Relaxed),
+            scans: self.requests[Operation::SCAN as usize].load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            queue_full: header.queue_full.load(Ordering::Relaxed),
+            queue_depth: header.pending() as u64,
+            keys: hash_table.len() as u64,
+            memory_bytes: hash_table.memory_usage() as u64,
+            chain_lengths: Stats::chain_histogram(hash_table.chain_lengths()),
+            workers: self.workers as u64,
+            busy_workers: self.busy_workers.load(Ordering::Relaxed) as u64,
+            utilization: if available_us > 0.0 {
+                self.busy_us.load(Ordering::Relaxed) as f64 * 100.0 / available_us
+            } else {
+                0.0
+            },
+        }
+    }
+}
 
-            // Calculate where to read the request from
-            let requests_ptr = ptr.add(size_of::<Header>());
-            let request_slot = requests_ptr.add(read_index * size_of::<Request>()) as *const Request;
-            
-            // Read the request
-            let request = ptr::read(request_slot);
-            
-            // Update read index
-            *read_index_guard = (read_index + 1) % CAPACITY;
-
-            println!("Server: Received request at position {} - {}", *write_guard, request);
-            return Ok(request);
+/// Takes the next request off the ring, or `None` if the ring is empty.
+pub fn get_request(segment: &Segment) -> Option<Request> {
+    loop {
+        match segment.try_dequeue() {
+            Dequeued::Request(request) => {
+                debug!(
+                    client:% = request.client,
+                    request_id = request.request_id,
+                    operation:? = request.operation,
+                    pending = segment.header().pending();
+                    "dequeued request");
+                return Some(request);
+            },
+            Dequeued::Skipped { owner } => {
+                warn!(owner; "skipping request slot abandoned by crashed client");
+                continue;
+            },
+            Dequeued::Empty => return None,
         }
     }
 }
 
-pub fn process_request(request: Request, hash_table: Arc<HashTable>) -> Result<(), Box<dyn Error>> {
-    println!("Processing request: {}", request);
+fn process_request(request: Request, hash_table: Arc<HashTable>, segment: &Segment, stats: &ServerStats) -> Result<(ReplyStatus, String), Box<dyn Error>> {
+    stats.requests[request.operation as usize].fetch_add(1, Ordering::Relaxed);
     // Process the request based on operation type
     match request.operation {
         Operation::INSERT => {
-            println!("Inserting key: {}", request.key_str());
-            hash_table.insert(request.key_str(), request.value_str());
+            if hash_table.insert(request.key_str(), request.value_str()) {
+                Ok((ReplyStatus::Ok, String::new()))
+            } else {
+                Ok((ReplyStatus::Full, String::new()))
+            }
         },
         Operation::DELETE => {
-            println!("Deleting key: {}", request.key_str());
-            let result = hash_table.delete(request.key_str());
-            if result {
-                println!("Key deleted successfully");
-            } else {    
-                println!("Key not found: {}", request.key_str());
+            if hash_table.delete(request.key_str()) {
+                Ok((ReplyStatus::Ok, String::new()))
+            } else {
+                Ok((ReplyStatus::NotFound, String::new()))
             }
         },
         Operation::GET => {
-            println!("Getting key: {}", request.key_str());
             match hash_table.get(request.key_str()) {
-                Some(value) => println!("Value: {}", value),
-                None => println!("Key not found: {}", request.key_str()),
+                Some(value) => {
+                    stats.hits.fetch_add(1, Ordering::Relaxed);
+                    Ok((ReplyStatus::Ok, value))
+                },
+                None => {
+                    stats.misses.fetch_add(1, Ordering::Relaxed);
+                    Ok((ReplyStatus::NotFound, String::new()))
+                },
             }
         },
+        Operation::STATS => match request.key_str().parse().ok().and_then(|page| stats.snapshot(&hash_table, segment).encode_page(page)) {
+            Some(page) => Ok((ReplyStatus::Ok, page)),
+            None => Ok((ReplyStatus::NotFound, String::new())),
+        },
+        Operation::SCAN => match request.key_str().parse() {
+            Ok(cursor) => Ok((ReplyStatus::Ok, ScanPage::collect(&hash_table, cursor).encode())),
+            // Still reply, so the client doesn't wait for the timeout
+            Err(_) => Ok((ReplyStatus::NotFound, String::new())),
+        },
+    }
+}
+
+/// Time since the client submitted the request.
+fn request_latency(request: &Request) -> Duration {
+    let now_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+    Duration::from_micros(now_us.saturating_sub(request.timestamp_us))
+}
+
+/// Starts exporting the metrics in the background as configured.
+fn start_metrics_exporters(config: &MetricsConfig, hash_table: &Arc<HashTable>, segment: &Arc<Segment>, stats: &Arc<ServerStats>) -> std::io::Result<()> {
+    let render = {
+        let (hash_table, segment, stats) = (hash_table.clone(), segment.clone(), stats.clone());
+        move || metrics::render(&stats.snapshot(&hash_table, &segment), &stats.latencies)
+    };
+    if let Some(port) = config.port {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!(address:% = listener.local_addr()?; "serving metrics");
+        let render = render.clone();
+        std::thread::spawn(move || metrics::serve(listener, render));
     }
+    if let Some(path) = config.file.clone() {
+        let interval = Duration::from_secs(config.interval);
+        std::thread::spawn(move || loop {
+            if let Err(e) = metrics::write_textfile(&path, &render()) {
+                warn!(path:% = path.display(), error:% = e; "failed to write metrics file");
+            }
+            std::thread::sleep(interval);
+        });
+    }
+    Ok(())
+}
+
+/// Where the reply to a request goes.
+enum ReplyTo {
+    /// The reply slot named in the request.
+    Segment,
+    /// The socket connection the request arrived on.
+    Socket(Responder),
+}
+
+/// A request read from a socket connection, waiting to be dispatched by the main loop.
+type SocketRequest = (Request, ReplyTo);
+
+/// Writes the outcome of a request into the reply slot the client is waiting on,
+/// or sends it back over the client's connection.
+fn send_reply(segment: &Segment, request: &Request, reply_to: &ReplyTo, status: ReplyStatus, value: &str) {
+    if let ReplyTo::Socket(responder) = reply_to {
+        let reply = Reply { request_id: request.request_id, status, value: value.to_string() };
+        if let Err(e) = responder.send(&reply) {
+            // The client hung up or stopped taking its replies
+            debug!(client:% = request.client, request_id = request.request_id, error:% = e; "failed to send reply");
+        }
+        return;
+    }
+    if request.reply_slot == shared_serve::NO_REPLY {
+        return;
+    }
+    match segment.reply_slot(request.reply_slot as usize) {
+        Some(slot) => slot.complete(request.client.pid, request.request_id, status, value),
+        None => warn!(client:% = request.client, reply_slot = request.reply_slot; "invalid reply slot"),
+    }
+}
+
+/// Binds the Unix domain socket and accepts connections on it in the background.
+/// Their requests are sent to the main loop, which dispatches them together with
+/// the requests of the ring.
+fn start_socket_listener(config: &SocketConfig, mode: u32, requests: Sender<SocketRequest>) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &config.path else {
+        return Ok(());
+    };
+    if UnixStream::connect(path).is_ok() {
+        return Err(format!("{} belongs to a running server", path.display()).into());
+    }
+    // Left behind by a server that didn't shut down cleanly
+    remove_socket(path);
+    let listener = UnixListener::bind(path)?;
+    // Admit the same users as the shared memory segment
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    info!(path:% = path.display(); "listening on socket");
+    std::thread::spawn(move || {
+        wire::serve(listener.incoming(), ConnectionLimits::default(), move |request, responder| {
+            // The main loop only stops receiving once the server exits
+            let _ = requests.send((request, ReplyTo::Socket(responder)));
+        })
+    });
+    Ok(())
+}
+
+/// Like `start_socket_listener`, for remote clients connecting over TCP.
+fn start_tcp_listener(config: &TcpConfig, requests: Sender<SocketRequest>) -> Result<(), Box<dyn Error>> {
+    let Some(address) = config.address else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(address)?;
+    let limits = ConnectionLimits {
+        max_connections: config.max_connections,
+        idle_timeout: (config.idle_timeout > 0).then(|| Duration::from_secs(config.idle_timeout)),
+    };
+    info!(address:% = listener.local_addr()?, max_connections = limits.max_connections; "listening on TCP");
+    std::thread::spawn(move || {
+        wire::serve(listener.incoming(), limits, move |request, responder| {
+            let _ = requests.send((request, ReplyTo::Socket(responder)));
+        })
+    });
     Ok(())
 }
 
-fn cleanup(ptr: *mut u8) {
-    eprintln!("Cleaning up...");
-    unsafe {
-        // Unmap the shared memory
-        if let Err(e) = mman::munmap(
-            std::ptr::NonNull::new(ptr as *mut _).unwrap(),
-            SHARED_MEMORY_SIZE
-        ) {
-            eprintln!("Error unmapping shared memory: {}", e);
+fn remove_socket(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!(path:% = path.display(), error:% = e; "failed to remove socket");
+        }
+    }
+}
+
+/// Hands the request to the worker responsible for its key.
+fn dispatch(workers: &Dispatcher, hash_table: &Arc<HashTable>, segment: &Arc<Segment>, stats: &Arc<ServerStats>, request: Request, reply_to: ReplyTo) {
+    // Requests on the same bucket go to the same worker, keeping them in order.
+    // The dispatcher also keeps requests of one client in submission order.
+    let bucket = hash_table.get_bucket(request.key_str());
+    let hash_table = hash_table.clone();
+    let segment = segment.clone();
+    let stats = stats.clone();
+    workers.execute(request.client, bucket, move || {
+        let started = Instant::now();
+        stats.busy_workers.fetch_add(1, Ordering::Relaxed);
+        match process_request(request, hash_table, &segment, &stats) {
+            Ok((status, value)) => {
+                debug!(
+                    client:% = request.client,
+                    request_id = request.request_id,
+                    operation:? = request.operation,
+                    key = request.key_str(),
+                    status:? = status;
+                    "processed request");
+                send_reply(&segment, &request, &reply_to, status, &value);
+            },
+            Err(e) => error!(client:% = request.client, request_id = request.request_id, error:% = e; "failed to process request"),
+        }
+        stats.latencies[request.operation as usize].observe(request_latency(&request));
+        stats.busy_workers.fetch_sub(1, Ordering::Relaxed);
+        stats.busy_us.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    });
+}
+
+/// Processes the requests still in the ring until it is empty, the deadline
+/// passes or a second interrupt arrives. Requests already read from a
+/// socket are processed as well.
+fn drain(workers: &Dispatcher, hash_table: &Arc<HashTable>, segment: &Arc<Segment>, stats: &Arc<ServerStats>, socket_requests: &Receiver<SocketRequest>, deadline: Instant) {
+    while let Ok((request, reply_to)) = socket_requests.try_recv() {
+        dispatch(workers, hash_table, segment, stats, request, reply_to);
+    }
+    info!(pending = segment.header().pending(); "draining queue");
+    while segment.header().pending() > 0 {
+        if immediate_exit_requested() {
+            warn!(pending = segment.header().pending(); "second interrupt received, skipping drain");
+            return;
+        }
+        if Instant::now() >= deadline {
+            warn!(pending = segment.header().pending(); "drain timed out");
+            return;
         }
+        match get_request(segment) {
+            Some(request) => dispatch(workers, hash_table, segment, stats, request, ReplyTo::Segment),
+            // A client is still copying its request in
+            None => std::thread::sleep(Duration::from_millis(1)),
+        }
+    }
+}
+
+fn cleanup(segment: Arc<Segment>, name: &str, preserve_queue: bool) {
+    if preserve_queue {
+        segment.preserve();
+        info!(pending = segment.header().pending(); "leaving segment in place");
+        return;
     }
+    // Unmap the shared memory once the last worker lets go of it
+    drop(segment);
     // Unlink the shared memory object
-    if let Err(e) = mman::shm_unlink("RequestQueue") {
-        eprintln!("Error unlinking shared memory: {}", e);
+    if let Err(e) = Segment::unlink(name) {
+        error!(name, error:% = e; "failed to unlink shared memory");
     }
-    eprintln!("Cleanup complete. Exiting.");
+    info!("cleanup complete");
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     
     let args = Args::parse();
-    let hash_table_size = args.size;
-    let thread_count = args.num_threads;
-
-    let hash_table = Arc::new(HashTable::new(hash_table_size));
-    let ptr = setup_shared_memory_server().expect("Failed to set up shared memory");
-
-    
+    let (config, unknown_vars) = match load_config(&args) {
+        Ok(loaded) => loaded,
+        Err(errors) => {
+            // The logger isn't set up yet, and its settings may be among the invalid ones
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            std::process::exit(2);
+        }
+    };
+    Logger::new(config.logging.level, config.logging.format).init()?;
+    for variable in unknown_vars {
+        warn!(variable; "ignoring environment variable that names no setting");
+    }
+    let hash_table_size = config.table.size;
+    let thread_count = config.workers.threads;
+    let name = config.segment.name.as_str();
+    let preserve_queue = config.persistence.preserve_queue;
 
-    let threads = ThreadPool::new(thread_count);
+    let hash_table = Arc::new(HashTable::with_limit(hash_table_size, config.table.max_keys, config.table.eviction));
+    let (segment, startup) = Segment::create_with_capacity(name, config.persistence.recover, config.segment.mode, config.segment.capacity)
+        .map_err(|e| format!("Failed to set up shared memory: {}", e))?;
+    match startup {
+        Startup::Created => {},
+        Startup::Reinitialized { previous_pid: 0 } => {
+            warn!("reinitialized existing segment with another layout or ring capacity");
+        },
+        Startup::Reinitialized { previous_pid } => {
+            warn!(previous_pid; "reinitialized stale segment left behind by crashed server");
+        },
+        Startup::Recovered { previous_pid, pending } => {
+            warn!(previous_pid, pending; "recovered segment of crashed server");
+        },
+        Startup::Resumed { pending } => {
+            info!(pending; "resumed preserved segment");
+        },
+    }
+    let segment = Arc::new(segment);
 
-    let (shutdown_tx, shutdown_rx) = channel();
+    let workers = Dispatcher::new(thread_count);
+    let stats = Arc::new(ServerStats::new(thread_count));
+    if let Err(e) = start_metrics_exporters(&config.metrics, &hash_table, &segment, &stats) {
+        cleanup(segment, name, preserve_queue);
+        return Err(format!("Failed to start metrics exporter: {}", e).into());
+    }
+    // The main loop holds on to a sender, so receiving only fails on a timeout
+    let (socket_sender, socket_requests) = channel();
+    if let Err(e) = start_socket_listener(&config.socket, config.segment.mode, socket_sender.clone()) {
+        cleanup(segment, name, preserve_queue);
+        return Err(format!("Failed to listen on socket: {}", e).into());
+    }
+    if let Err(e) = start_tcp_listener(&config.tcp, socket_sender.clone()) {
+        cleanup(segment, name, preserve_queue);
+        if let Some(path) = &config.socket.path {
+            remove_socket(path);
+        }
+        return Err(format!("Failed to listen on TCP: {}", e).into());
+    }
 
-    ctrlc::set_handler(move || shutdown_tx.send(()).expect("Could not send signal on channel."))
-        .expect("Error setting Ctrl-C handler");
+    install_signal_handlers()?;
 
-    println!("Server started with {} threads. Waiting for requests...", thread_count);
-    println!("=====================================================");
-    loop {
-        if shutdown_rx.try_recv().is_ok() {
-            eprintln!("Shutdown signal received.");
-            threads.join();
-            break;
-        }
-        
-        match get_request(ptr) {
-            Ok(request) => {
-                let hash_table = hash_table.clone();
-                threads.execute(move || {
-                    if let Err(e) = process_request(request, hash_table) {
-                        eprintln!("Error processing request: {}", e);
-                    }
-                    println!("=====================================================");
-                });
-            },
-            Err(e) => {
-                if e.to_string() == "Server: Queue is empty" {
-                    std::thread::sleep(std::time::Duration::from_millis(100));
-                    continue;
-                }
-                else {
-                    eprintln!("Error: {}", e);
+    info!(name, threads = thread_count; "server started");
+    while !shutdown_requested() {
+        match get_request(&segment) {
+            Some(request) => dispatch(&workers, &hash_table, &segment, &stats, request, ReplyTo::Segment),
+            None => {
+                // Wait for a request from a socket instead of sleeping
+                if let Ok((request, reply_to)) = socket_requests.recv_timeout(Duration::from_millis(1)) {
+                    dispatch(&workers, &hash_table, &segment, &stats, request, reply_to);
                 }
+                continue;
             }
         }
+        // Don't let a busy ring starve the sockets
+        while let Ok((request, reply_to)) = socket_requests.try_recv() {
+            dispatch(&workers, &hash_table, &segment, &stats, request, reply_to);
+        }
     }
 
-    
-    cleanup(ptr);
+    info!("shutdown signal received");
+    // Waiting for a client that holds the write lock counts towards the drain timeout
+    let deadline = Instant::now() + Duration::from_secs(config.persistence.drain_timeout);
+    if let Some(owner) = segment.begin_shutdown(|| immediate_exit_requested() || Instant::now() >= deadline) {
+        warn!(owner; "stopped waiting for client holding the write lock");
+    }
+    // A preserved queue is left for the next server instead
+    if !preserve_queue {
+        drain(&workers, &hash_table, &segment, &stats, &socket_requests, deadline);
+    }
+
+    if immediate_exit_requested() {
+        // Don't wait for the workers to finish what they already have
+        cleanup(segment, name, preserve_queue);
+        if let Some(path) = &config.socket.path {
+            remove_socket(path);
+        }
+        std::process::exit(130);
+    }
+    workers.join();
+
+    cleanup(segment, name, preserve_queue);
+    if let Some(path) = &config.socket.path {
+        remove_socket(path);
+    }
 
     Ok(())
 }
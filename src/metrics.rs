@@ -0,0 +1,192 @@
+use crate::{Operation, Stats};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in microseconds.
+pub const LATENCY_BUCKETS_US: [u64; 12] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000];
+
+/// The operations in the order of their discriminants.
+pub const OPERATIONS: [Operation; 5] = [Operation::GET, Operation::INSERT, Operation::DELETE, Operation::STATS, Operation::SCAN];
+
+/// A latency histogram that can be updated from several workers at once.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    /// Observations per bucket, not cumulative. The last entry counts observations above all bounds.
+    buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&self, latency: Duration) {
+        let latency_us = latency.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US.partition_point(|&bound| bound < latency_us);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(latency_us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+fn operation_label(operation: Operation) -> &'static str {
+    match operation {
+        Operation::GET => "get",
+        Operation::INSERT => "insert",
+        Operation::DELETE => "delete",
+        Operation::STATS => "stats",
+        Operation::SCAN => "scan",
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Formats the counters and the latency histogram of each operation, indexed
+/// like `OPERATIONS`, in the Prometheus text exposition format.
+pub fn render(stats: &Stats, latencies: &[LatencyHistogram; OPERATIONS.len()]) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "shared_serve_requests_total", "counter", "Requests processed by operation.");
+    let requests = [stats.gets, stats.inserts, stats.deletes, stats.stats, stats.scans];
+    for (operation, count) in OPERATIONS.iter().zip(requests) {
+        let _ = writeln!(out, "shared_serve_requests_total{{operation=\"{}\"}} {}", operation_label(*operation), count);
+    }
+
+    let counters = [
+        ("shared_serve_get_hits_total", "GET requests that found their key.", stats.hits),
+        ("shared_serve_get_misses_total", "GET requests that did not find their key.", stats.misses),
+        ("shared_serve_queue_full_total", "Reservations rejected because the request queue was full.", stats.queue_full),
+    ];
+    for (name, help, value) in counters {
+        header(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    let gauges = [
+        ("shared_serve_queue_depth", "Requests waiting in the request queue.", stats.queue_depth as f64),
+        ("shared_serve_keys", "Keys stored in the hash table.", stats.keys as f64),
+        ("shared_serve_memory_bytes", "Bytes taken up by the stored keys and values.", stats.memory_bytes as f64),
+        ("shared_serve_workers", "Worker threads.", stats.workers as f64),
+        ("shared_serve_busy_workers", "Workers processing a request.", stats.busy_workers as f64),
+        ("shared_serve_worker_utilization_ratio", "Share of worker time spent processing requests.", stats.utilization / 100.0),
+    ];
+    for (name, help, value) in gauges {
+        header(&mut out, name, "gauge", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    header(&mut out, "shared_serve_bucket_chains", "gauge", "Buckets by the number of keys in their chain.");
+    for (length, count) in stats.chain_lengths.iter().enumerate() {
+        let plus = if length == stats.chain_lengths.len() - 1 { "+" } else { "" };
+        let _ = writeln!(out, "shared_serve_bucket_chains{{length=\"{}{}\"}} {}", length, plus, count);
+    }
+
+    let name = "shared_serve_request_duration_seconds";
+    header(&mut out, name, "histogram", "Time from submission by the client until the reply was written.");
+    for (operation, histogram) in OPERATIONS.iter().zip(latencies) {
+        let label = operation_label(*operation);
+        // Workers may observe while this runs, so the total is taken from the
+        // bucket values read here rather than from `count`, which could lag them
+        let buckets: Vec<u64> = histogram.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS_US.iter().zip(&buckets) {
+            cumulative += bucket;
+            let _ = writeln!(out, "{}_bucket{{operation=\"{}\",le=\"{}\"}} {}", name, label, *bound as f64 / 1e6, cumulative);
+        }
+        let count: u64 = buckets.iter().sum();
+        let _ = writeln!(out, "{}_bucket{{operation=\"{}\",le=\"+Inf\"}} {}", name, label, count);
+        let _ = writeln!(out, "{}_sum{{operation=\"{}\"}} {}", name, label, histogram.sum_us.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(out, "{}_count{{operation=\"{}\"}} {}", name, label, count);
+    }
+    out
+}
+
+/// Answers `GET /metrics` on the listener with the output of `render` until
+/// the listener fails. Meant to run on a thread of its own.
+pub fn serve<F: Fn() -> String>(listener: TcpListener, render: F) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = respond(stream, &render) {
+                    log::warn!(error:% = e; "failed to answer metrics request");
+                }
+            }
+            Err(e) => {
+                log::error!(error:% = e; "stopped serving metrics");
+                return;
+            }
+        }
+    }
+}
+
+fn respond<F: Fn() -> String>(stream: TcpStream, render: &F) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, the request has no body
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", String::from("Not found\n")),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Replaces the file at `path` with `contents`. The file is written next to
+/// it first and then renamed, so a collector never reads a partial file.
+pub fn write_textfile(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)
+}
+
+// Unit tests for the metrics exporter
+#[test]
+fn test_latency_histogram_buckets() {
+    let histogram = LatencyHistogram::default();
+    histogram.observe(Duration::from_micros(50));
+    histogram.observe(Duration::from_micros(51));
+    histogram.observe(Duration::from_secs(1));
+    assert_eq!(histogram.buckets[0].load(Ordering::Relaxed), 1);
+    assert_eq!(histogram.buckets[1].load(Ordering::Relaxed), 1);
+    assert_eq!(histogram.buckets[LATENCY_BUCKETS_US.len()].load(Ordering::Relaxed), 1);
+    assert_eq!(histogram.count(), 3);
+}
+
+#[test]
+fn test_render_histogram_is_cumulative() {
+    let latencies: [LatencyHistogram; OPERATIONS.len()] = Default::default();
+    latencies[Operation::INSERT as usize].observe(Duration::from_micros(80));
+    latencies[Operation::INSERT as usize].observe(Duration::from_micros(700));
+    let stats = Stats { inserts: 2, ..Stats::default() };
+    let text = render(&stats, &latencies);
+    assert!(text.contains("shared_serve_requests_total{operation=\"insert\"} 2\n"));
+    assert!(text.contains("shared_serve_request_duration_seconds_bucket{operation=\"insert\",le=\"0.0001\"} 1\n"));
+    assert!(text.contains("shared_serve_request_duration_seconds_bucket{operation=\"insert\",le=\"0.001\"} 2\n"));
+    assert!(text.contains("shared_serve_request_duration_seconds_bucket{operation=\"insert\",le=\"+Inf\"} 2\n"));
+    assert!(text.contains("shared_serve_request_duration_seconds_count{operation=\"get\"} 0\n"));
+}
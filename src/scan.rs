@@ -0,0 +1,139 @@
+use crate::command::{quote, tokenize, HEX_PREFIX};
+use crate::HashTable;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// Most bytes of a reply value, which a page must fit into.
+const PAGE_LEN: usize = 256;
+/// Room kept for the cursor at the start of a page: two u64 and a colon.
+const CURSOR_LEN: usize = 41;
+/// Marks the last page in place of the cursor.
+const END: &str = "end";
+
+/// Where a SCAN continues: a position in the chain of a bucket.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ScanCursor {
+    pub bucket: usize,
+    pub position: usize,
+}
+
+impl fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.bucket, self.position)
+    }
+}
+
+impl FromStr for ScanCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bucket, position) = s.split_once(':').ok_or_else(|| format!("invalid cursor `{}`", s))?;
+        Ok(ScanCursor {
+            bucket: bucket.parse().map_err(|_| format!("invalid cursor `{}`", s))?,
+            position: position.parse().map_err(|_| format!("invalid cursor `{}`", s))?,
+        })
+    }
+}
+
+/// Keys returned by one SCAN request.
+///
+/// The server sends the cursor of the next page, or `end`, followed by the
+/// keys as tokens of the client's text protocol, all of which must fit into
+/// the value of a reply.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanPage {
+    pub keys: Vec<String>,
+    /// Where the next page starts, `None` once every key was returned.
+    pub next: Option<ScanCursor>,
+}
+
+impl ScanPage {
+    /// Takes as many keys from `cursor` on as fit into a reply. At least one
+    /// key always fits, as keys are at most 64 bytes long.
+    pub fn collect(table: &HashTable, cursor: ScanCursor) -> Self {
+        let mut keys = Vec::new();
+        let mut len = CURSOR_LEN;
+        let next = table.scan(cursor, |key| {
+            let token_len = encode_key(key).len() + 1;
+            if len + token_len > PAGE_LEN {
+                return false;
+            }
+            len += token_len;
+            keys.push(key.to_string());
+            true
+        });
+        ScanPage { keys, next }
+    }
+
+    /// The wire format sent in the reply to a SCAN request.
+    pub fn encode(&self) -> String {
+        let mut encoded = match self.next {
+            Some(cursor) => cursor.to_string(),
+            None => END.to_string(),
+        };
+        for key in &self.keys {
+            encoded.push(' ');
+            encoded.push_str(&encode_key(key));
+        }
+        encoded
+    }
+
+    /// Parses the output of `encode`.
+    pub fn parse(encoded: &str) -> Option<Self> {
+        let mut tokens = tokenize(encoded).ok()?.into_iter();
+        let next = match tokens.next()?.as_str() {
+            END => None,
+            cursor => Some(cursor.parse().ok()?),
+        };
+        Some(ScanPage { keys: tokens.collect(), next })
+    }
+}
+
+/// The shorter of the quoted key and its hex literal, so keys full of
+/// escaped characters still fit into a page.
+fn encode_key(key: &str) -> String {
+    let quoted = quote(key);
+    if quoted.len() <= HEX_PREFIX.len() + 2 * key.len() {
+        return quoted;
+    }
+    key.bytes().fold(HEX_PREFIX.to_string(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+// Unit tests for the scan wire format
+#[test]
+fn test_scan_page_round_trip() {
+    let page = ScanPage {
+        keys: vec!["plain".into(), "with space".into(), "\x01\x02\x03\x04".into(), String::new()],
+        next: Some(ScanCursor { bucket: 3, position: 1 }),
+    };
+    assert!(page.encode().contains("hex:01020304"));
+    assert_eq!(ScanPage::parse(&page.encode()), Some(page));
+    let last = ScanPage { keys: vec!["k".into()], next: None };
+    assert_eq!(last.encode(), "end k");
+    assert_eq!(ScanPage::parse(&last.encode()), Some(last));
+    assert_eq!(ScanPage::parse("nonsense k"), None);
+}
+
+#[test]
+fn test_scan_pages_cover_table() {
+    let table = HashTable::new(4);
+    let mut keys: Vec<String> = (0..50).map(|i| format!("{:\x01>64}", i)).collect();
+    keys.sort();
+    for key in &keys {
+        table.insert(key, "value");
+    }
+    let mut scanned = Vec::new();
+    let mut cursor = Some(ScanCursor::default());
+    while let Some(start) = cursor {
+        let page = ScanPage::collect(&table, start);
+        assert!(!page.keys.is_empty());
+        assert!(page.encode().len() <= PAGE_LEN);
+        scanned.extend(page.keys);
+        cursor = page.next;
+    }
+    scanned.sort();
+    assert_eq!(scanned, keys);
+}
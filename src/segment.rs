@@ -0,0 +1,850 @@
+use crate::{ClientId, Request};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::libc::off_t;
+use nix::sys::signal;
+use nix::sys::stat::{fchmod, fstat, Mode};
+use nix::sys::{mman, mman::MapFlags, mman::ProtFlags};
+use nix::unistd::{ftruncate, Pid};
+use std::cell::UnsafeCell;
+use std::ffi::c_void;
+use std::fmt;
+use std::mem::size_of;
+use std::num::NonZero;
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::fs::FileExt;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// Name of the shared memory object used when none is given.
+pub const DEFAULT_SEGMENT_NAME: &str = "RequestQueue";
+/// Identifies a shared_serve segment ("SHSERVE" followed by a zero byte).
+pub const SEGMENT_MAGIC: u64 = u64::from_le_bytes(*b"SHSERVE\0");
+/// Bumped whenever the layout of the segment changes.
+pub const SEGMENT_VERSION: u32 = 5;
+
+/// Number of request slots in the ring of a segment created by `Segment::create`.
+/// The ring holds one request less, since a full ring would look empty.
+pub const DEFAULT_CAPACITY: usize = 10;
+/// Most request slots a ring can have.
+pub const MAX_CAPACITY: usize = 1 << 16;
+/// Permissions of a segment created by `Segment::create`: read-write for the owner only.
+pub const DEFAULT_SEGMENT_MODE: u32 = 0o600;
+/// Number of slots clients can claim to receive the server's response.
+pub const REPLY_SLOTS: usize = 64;
+/// Number of clients that can be connected at the same time.
+pub const MAX_CLIENTS: usize = 128;
+
+/// Size of the shared memory object of a segment whose ring has `capacity` slots.
+pub const fn segment_size(capacity: usize) -> usize {
+    size_of::<Header>()
+        + size_of::<RequestSlot>() * capacity
+        + size_of::<ReplySlot>() * REPLY_SLOTS
+        + size_of::<AtomicU32>() * MAX_CLIENTS
+}
+
+/// A server owns the segment, or owned it until it crashed.
+pub const STATE_SERVING: u8 = 0;
+/// The last server shut down and left its pending requests for the next one.
+pub const STATE_PRESERVED: u8 = 1;
+/// The server is draining the queue before exiting and accepts no new requests.
+pub const STATE_SHUTTING_DOWN: u8 = 2;
+
+#[repr(C)]
+pub struct Header {
+    pub magic: u64,
+    pub version: u32,
+    /// Pid of the server that owns the segment.
+    pub server_pid: AtomicU32,
+    /// One of the `STATE_*` constants.
+    pub state: AtomicU8,
+    /// Number of request slots in the ring, set when the segment is created.
+    pub capacity: u32,
+    /// Bumped whenever a server resets or takes over the segment, which
+    /// invalidates the registrations of attached clients.
+    pub generation: AtomicU64,
+    pub read_index: AtomicUsize,
+    pub write_index: AtomicUsize,
+    /// Pid of the client currently reserving a slot, 0 if unlocked.
+    pub write_lock: AtomicU32,
+    /// Reservations rejected because the ring was full.
+    pub queue_full: AtomicU64,
+}
+
+impl Header {
+    pub fn new(capacity: usize) -> Self {
+        Header {
+            magic: SEGMENT_MAGIC,
+            version: SEGMENT_VERSION,
+            server_pid: AtomicU32::new(0),
+            state: AtomicU8::new(STATE_SERVING),
+            capacity: capacity as u32,
+            generation: AtomicU64::new(1),
+            read_index: AtomicUsize::new(0),
+            write_index: AtomicUsize::new(0),
+            write_lock: AtomicU32::new(0),
+            queue_full: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the header was written by a server using the same layout.
+    pub fn is_valid(&self) -> bool {
+        self.magic == SEGMENT_MAGIC && self.version == SEGMENT_VERSION
+    }
+
+    /// Number of requests between the read and the write index.
+    pub fn pending(&self) -> usize {
+        let read_index = self.read_index.load(Ordering::Acquire);
+        let write_index = self.write_index.load(Ordering::Acquire);
+        let capacity = self.capacity as usize;
+        (write_index + capacity - read_index) % capacity
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+const REQUEST_EMPTY: u8 = 0;
+const REQUEST_RESERVED: u8 = 1;
+const REQUEST_COMMITTED: u8 = 2;
+
+/// A slot of the request ring.
+///
+/// Clients reserve a slot while holding the write lock, then copy the request
+/// in and commit it after releasing the lock. The server only reads committed
+/// slots, so a client dying halfway through the copy never hands it a torn
+/// request.
+#[repr(C)]
+pub struct RequestSlot {
+    state: AtomicU8,
+    owner: AtomicU32,
+    request: UnsafeCell<Request>,
+}
+
+impl RequestSlot {
+    /// Copies the request into a reserved slot and publishes it to the server.
+    pub fn commit(&self, request: &Request) {
+        unsafe { ptr::write_volatile(self.request.get(), *request) };
+        self.state.store(REQUEST_COMMITTED, Ordering::Release);
+    }
+
+    /// Pid of the client that reserved the slot.
+    pub fn owner(&self) -> u32 {
+        self.owner.load(Ordering::Relaxed)
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == REQUEST_COMMITTED
+    }
+
+    /// Whether a client reserved the slot but has not committed its request yet.
+    pub fn is_reserved(&self) -> bool {
+        self.state.load(Ordering::Acquire) == REQUEST_RESERVED
+    }
+
+    /// Reads the committed request. Only the server should call this.
+    pub fn read(&self) -> Request {
+        unsafe { ptr::read_volatile(self.request.get()) }
+    }
+}
+
+/// Outcome of an attempt to reserve a slot in the request ring.
+pub enum Reservation<'a> {
+    Reserved(&'a RequestSlot),
+    Full,
+    /// Another live client holds the write lock.
+    Locked,
+    /// The server is shutting down.
+    Closed,
+}
+
+/// Outcome of an attempt to take the next request off the ring.
+#[allow(clippy::large_enum_variant)] // Requests are copied around by value everywhere else too
+pub enum Dequeued {
+    Request(Request),
+    /// Nothing is committed yet at the read index.
+    Empty,
+    /// The slot was reserved by a client that died before committing it.
+    Skipped { owner: u32 },
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplyStatus {
+    Ok = 0,
+    NotFound = 1,
+    /// An INSERT of a new key was rejected because the table holds its maximum number of keys.
+    Full = 2,
+}
+
+/// The server's answer to a request, tagged with the id of the request it answers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub request_id: u64,
+    pub status: ReplyStatus,
+    pub value: String,
+}
+
+const SLOT_FREE: u8 = 0;
+const SLOT_PENDING: u8 = 1;
+const SLOT_WRITING: u8 = 2;
+const SLOT_READY: u8 = 3;
+const SLOT_ABANDONED: u8 = 4;
+
+/// Packs the owning pid and the slot state into one word so both change together.
+fn slot_word(owner: u32, state: u8) -> u64 {
+    ((owner as u64) << 32) | state as u64
+}
+
+fn slot_owner(word: u64) -> u32 {
+    (word >> 32) as u32
+}
+
+fn slot_state(word: u64) -> u8 {
+    word as u8
+}
+
+/// A slot the server writes the outcome of a request into.
+///
+/// A client claims a free slot, stores its index in the `Request` and waits
+/// for the server to mark it ready. If the client gives up waiting, the slot
+/// is marked abandoned and the server frees it once the reply is written.
+/// Slots of clients that died are reclaimed by the next client looking for
+/// one; the server then drops the late reply because the owner has changed.
+#[repr(C)]
+pub struct ReplySlot {
+    word: AtomicU64,
+    status: AtomicU8,
+    request_id: AtomicU64,
+    value: UnsafeCell<[u8; 256]>,
+}
+
+impl ReplySlot {
+    /// Claims the slot for the calling process if it is free or its owner died.
+    pub fn try_claim(&self) -> bool {
+        let current = self.word.load(Ordering::Acquire);
+        let reclaimable = match slot_state(current) {
+            SLOT_FREE => true,
+            // The server is about to publish a reply, the slot will change shortly
+            SLOT_WRITING => false,
+            _ => !process_alive(slot_owner(current)),
+        };
+        reclaimable && self
+            .word
+            .compare_exchange(current, slot_word(std::process::id(), SLOT_PENDING), Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Releases a claimed slot that was never handed to the server.
+    pub fn release(&self) {
+        self.word.store(slot_word(0, SLOT_FREE), Ordering::Release);
+    }
+
+    /// Called by the server to publish the reply for a slot claimed by `owner`.
+    pub fn complete(&self, owner: u32, request_id: u64, status: ReplyStatus, value: &str) {
+        let current = self.word.load(Ordering::Acquire);
+        if slot_owner(current) != owner {
+            // The client died and its slot was reclaimed by someone else
+            return;
+        }
+        match slot_state(current) {
+            SLOT_PENDING => {
+                if self
+                    .word
+                    .compare_exchange(current, slot_word(owner, SLOT_WRITING), Ordering::AcqRel, Ordering::Relaxed)
+                    .is_err()
+                {
+                    // The client gave up or the slot was reclaimed meanwhile
+                    return self.complete(owner, request_id, status, value);
+                }
+            }
+            // Nobody is waiting for an abandoned slot, so hand it back directly.
+            SLOT_ABANDONED => {
+                let _ = self.word.compare_exchange(current, slot_word(0, SLOT_FREE), Ordering::AcqRel, Ordering::Relaxed);
+                return;
+            }
+            _ => return,
+        }
+
+        let mut buffer = [0u8; 256];
+        let len = value.len().min(256);
+        buffer[..len].copy_from_slice(&value.as_bytes()[..len]);
+        unsafe { ptr::write_volatile(self.value.get(), buffer) };
+        self.status.store(status as u8, Ordering::Relaxed);
+        self.request_id.store(request_id, Ordering::Relaxed);
+        self.word.store(slot_word(owner, SLOT_READY), Ordering::Release);
+    }
+
+    /// Takes the reply out of the slot and frees it, if the server has answered.
+    pub fn take(&self) -> Option<Reply> {
+        if slot_state(self.word.load(Ordering::Acquire)) != SLOT_READY {
+            return None;
+        }
+        let status = match self.status.load(Ordering::Relaxed) {
+            0 => ReplyStatus::Ok,
+            2 => ReplyStatus::Full,
+            _ => ReplyStatus::NotFound,
+        };
+        let request_id = self.request_id.load(Ordering::Relaxed);
+        let buffer = unsafe { ptr::read_volatile(self.value.get()) };
+        let value = Request::bytes_to_str(&buffer).to_string();
+        self.release();
+        Some(Reply { request_id, status, value })
+    }
+
+    /// Whether the server is writing or has written the reply.
+    pub fn is_answered(&self) -> bool {
+        matches!(slot_state(self.word.load(Ordering::Acquire)), SLOT_WRITING | SLOT_READY)
+    }
+
+    /// Gives up on a pending reply. Returns false if the reply already arrived
+    /// or is being written, in which case the caller should `take` it instead.
+    pub fn abandon(&self) -> bool {
+        let current = self.word.load(Ordering::Acquire);
+        slot_state(current) == SLOT_PENDING && self
+            .word
+            .compare_exchange(current, slot_word(slot_owner(current), SLOT_ABANDONED), Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+#[derive(Debug)]
+pub enum SegmentError {
+    Io(nix::Error),
+    /// The segment belongs to a server that is still running.
+    InUse { pid: u32 },
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::Io(e) => write!(f, "{}", e),
+            SegmentError::InUse { pid } => write!(f, "Segment is in use by running server {}", pid),
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {}
+
+impl From<nix::Error> for SegmentError {
+    fn from(e: nix::Error) -> Self {
+        SegmentError::Io(e)
+    }
+}
+
+/// How the server came to own its segment.
+#[derive(Debug, PartialEq)]
+pub enum Startup {
+    /// No segment existed yet.
+    Created,
+    /// A segment left behind by a crashed server was reset.
+    Reinitialized { previous_pid: u32 },
+    /// A segment left behind by a crashed server was taken over with its pending requests.
+    Recovered { previous_pid: u32, pending: usize },
+    /// A segment preserved by a server on shutdown was taken over with its pending requests.
+    Resumed { pending: usize },
+}
+
+/// Pid of the server that owns a segment of another layout, e.g. one created
+/// by another build. Every layout starts with the magic, the version and the
+/// server pid, so these can be read without mapping the rest.
+fn foreign_owner(shm_fd: &OwnedFd) -> Option<u32> {
+    const _: () = assert!(std::mem::offset_of!(Header, server_pid) == 12);
+    let file = File::from(shm_fd.try_clone().ok()?);
+    let mut prefix = [0u8; 16];
+    file.read_exact_at(&mut prefix, 0).ok()?;
+    if u64::from_ne_bytes(prefix[..8].try_into().unwrap()) != SEGMENT_MAGIC {
+        return None;
+    }
+    Some(u32::from_ne_bytes(prefix[12..].try_into().unwrap())).filter(|&pid| pid != 0)
+}
+
+/// A mapping of the shared memory segment. The mapping is removed on drop,
+/// the underlying shared memory object is only removed by `unlink`.
+pub struct Segment {
+    ptr: NonNull<c_void>,
+    len: usize,
+    /// Slots of the ring, checked against the size of the mapping.
+    capacity: usize,
+}
+
+// The segment only hands out references to types that synchronize themselves.
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    /// Creates the shared memory object for a server.
+    ///
+    /// If a segment with this name already exists, the server that created it
+    /// must no longer be running. Its segment is then either reset or, with
+    /// `recover_pending`, taken over together with the requests still queued.
+    pub fn create(name: &str, recover_pending: bool) -> Result<(Self, Startup), SegmentError> {
+        Self::create_with_mode(name, recover_pending, DEFAULT_SEGMENT_MODE)
+    }
+
+    /// Like `create`, but restricts access to the segment to the permission
+    /// bits in `mode`, e.g. 0o660 to let clients of the owner's group in.
+    pub fn create_with_mode(name: &str, recover_pending: bool, mode: u32) -> Result<(Self, Startup), SegmentError> {
+        Self::create_with_capacity(name, recover_pending, mode, DEFAULT_CAPACITY)
+    }
+
+    /// Like `create_with_mode`, with a ring of `capacity` request slots.
+    /// An existing segment with a ring of another size is reset, dropping
+    /// the requests it held.
+    pub fn create_with_capacity(name: &str, recover_pending: bool, mode: u32, capacity: usize) -> Result<(Self, Startup), SegmentError> {
+        assert!((2..=MAX_CAPACITY).contains(&capacity), "Ring capacity out of range");
+        let size = segment_size(capacity);
+        // shm_open applies the umask and an existing segment keeps its old
+        // mode, so the permissions are set explicitly either way
+        let mode = Mode::from_bits_truncate(mode);
+        let shm_fd = match mman::shm_open(
+            name,
+            OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR,
+            Mode::S_IRUSR | Mode::S_IWUSR) {
+            Ok(shm_fd) => {
+                fchmod(shm_fd.as_raw_fd(), mode)?;
+                ftruncate(shm_fd.as_fd(), size as off_t)?;
+                let segment = Self::map(shm_fd, capacity)?;
+                segment.initialize();
+                return Ok((segment, Startup::Created));
+            },
+            Err(Errno::EEXIST) => mman::shm_open(name, OFlag::O_RDWR, Mode::empty())?,
+            Err(e) => return Err(e.into()),
+        };
+
+        if fstat(shm_fd.as_raw_fd())?.st_size != size as off_t {
+            // Another layout or ring size, start over unless its server still uses it
+            if let Some(pid) = foreign_owner(&shm_fd).filter(|&pid| process_alive(pid)) {
+                return Err(SegmentError::InUse { pid });
+            }
+            fchmod(shm_fd.as_raw_fd(), mode)?;
+            ftruncate(shm_fd.as_fd(), size as off_t)?;
+            let segment = Self::map(shm_fd, capacity)?;
+            segment.initialize();
+            return Ok((segment, Startup::Reinitialized { previous_pid: 0 }));
+        }
+
+        let segment = Self::map(&shm_fd, capacity)?;
+        let header = segment.header();
+        if !header.is_valid() || header.capacity as usize != capacity {
+            if let Some(pid) = foreign_owner(&shm_fd).filter(|&pid| process_alive(pid)) {
+                return Err(SegmentError::InUse { pid });
+            }
+            fchmod(shm_fd.as_raw_fd(), mode)?;
+            segment.initialize();
+            return Ok((segment, Startup::Reinitialized { previous_pid: 0 }));
+        }
+
+        let previous_pid = header.server_pid.load(Ordering::Acquire);
+        if previous_pid != 0 && process_alive(previous_pid) {
+            return Err(SegmentError::InUse { pid: previous_pid });
+        }
+        // Only now that the segment is ours, so a refused server leaves the running one's alone
+        fchmod(shm_fd.as_raw_fd(), mode)?;
+
+        if header.state.load(Ordering::Acquire) == STATE_PRESERVED {
+            let pending = segment.take_over();
+            return Ok((segment, Startup::Resumed { pending }));
+        }
+
+        if !recover_pending {
+            segment.initialize();
+            return Ok((segment, Startup::Reinitialized { previous_pid }));
+        }
+
+        let pending = segment.take_over();
+        Ok((segment, Startup::Recovered { previous_pid, pending }))
+    }
+
+    /// Makes the calling process the owner of an existing segment and returns
+    /// the number of pending requests. The ring, reply slots and registrations
+    /// are kept as they are, since clients may still be waiting on them.
+    fn take_over(&self) -> usize {
+        let header = self.header();
+        let write_lock = header.write_lock.load(Ordering::Acquire);
+        if write_lock != 0 && !process_alive(write_lock) {
+            let _ = header.write_lock.compare_exchange(write_lock, 0, Ordering::AcqRel, Ordering::Relaxed);
+        }
+        header.server_pid.store(std::process::id(), Ordering::Release);
+        header.state.store(STATE_SERVING, Ordering::Release);
+        header.generation.fetch_add(1, Ordering::AcqRel);
+        header.pending()
+    }
+
+    /// Stops clients from enqueueing new requests. Waits until no client is
+    /// in the middle of a reservation, so no requests show up after the
+    /// pending ones are drained, or until `give_up` returns true. Returns
+    /// the pid of the client still holding the write lock if it gave up.
+    pub fn begin_shutdown<F: Fn() -> bool>(&self, give_up: F) -> Option<u32> {
+        let header = self.header();
+        header.state.store(STATE_SHUTTING_DOWN, Ordering::SeqCst);
+        loop {
+            let owner = header.write_lock.load(Ordering::SeqCst);
+            if owner == 0 || !process_alive(owner) {
+                return None;
+            }
+            if give_up() {
+                return Some(owner);
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Whether requests enqueued now will be processed, either by the live
+    /// server owning the segment or by the next server of a preserved one.
+    pub fn accepts_requests(&self) -> bool {
+        let header = self.header();
+        match header.state.load(Ordering::Acquire) {
+            STATE_PRESERVED => true,
+            STATE_SERVING => process_alive(header.server_pid.load(Ordering::Acquire)),
+            _ => false,
+        }
+    }
+
+    /// Hands the segment over to the next server instead of removing it.
+    /// The read index records where the next server continues draining the ring.
+    pub fn preserve(&self) {
+        let header = self.header();
+        header.state.store(STATE_PRESERVED, Ordering::Release);
+        header.server_pid.store(0, Ordering::Release);
+    }
+
+    /// Resets the segment to an empty queue owned by the calling process.
+    fn initialize(&self) {
+        let header = self.header();
+        let generation = if header.is_valid() { header.generation.load(Ordering::Acquire) + 1 } else { 1 };
+        unsafe {
+            ptr::write(self.as_ptr() as *mut Header, Header::new(self.capacity));
+            ptr::write_bytes(
+                self.as_ptr().add(size_of::<Header>()),
+                0,
+                self.len - size_of::<Header>());
+        }
+        self.header().generation.store(generation, Ordering::Release);
+        self.header().server_pid.store(std::process::id(), Ordering::Release);
+    }
+
+    /// Maps an existing shared memory object created by the server.
+    pub fn open(name: &str) -> nix::Result<Self> {
+        let shm_fd = mman::shm_open(
+            name,
+            OFlag::O_RDWR,
+            Mode::empty())?;
+        let segment = Self::map_existing(shm_fd, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)?;
+        if segment.capacity == 0 {
+            return Err(Errno::EINVAL);
+        }
+        Ok(segment)
+    }
+
+    /// Maps a segment without write access, for inspecting it while a server
+    /// and its clients use it. Unlike `open`, a header of another layout is
+    /// accepted so it can be looked at, but only the header may be read then
+    /// and `capacity` is 0.
+    ///
+    /// Only loads are allowed on the returned segment: anything that writes
+    /// to it, like reserving a slot or registering a client, faults.
+    pub fn open_read_only(name: &str) -> nix::Result<Self> {
+        let shm_fd = mman::shm_open(
+            name,
+            OFlag::O_RDONLY,
+            Mode::empty())?;
+        Self::map_existing(shm_fd, ProtFlags::PROT_READ)
+    }
+
+    /// Removes the shared memory object. Existing mappings stay valid.
+    pub fn unlink(name: &str) -> nix::Result<()> {
+        mman::shm_unlink(name)
+    }
+
+    fn map<F: AsFd>(shm_fd: F, capacity: usize) -> nix::Result<Self> {
+        let mut segment = Self::map_with(shm_fd, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, segment_size(capacity))?;
+        segment.capacity = capacity;
+        Ok(segment)
+    }
+
+    /// Maps a segment created by a server, taking the size of its ring from
+    /// the header. The capacity stays 0 unless the header is of this layout
+    /// and matches the size of the object.
+    fn map_existing<F: AsFd>(shm_fd: F, protection: ProtFlags) -> nix::Result<Self> {
+        let size = fstat(shm_fd.as_fd().as_raw_fd())?.st_size as usize;
+        // Mapping a smaller object would fault on access
+        if size < size_of::<Header>() {
+            return Err(Errno::EINVAL);
+        }
+        let mut segment = Self::map_with(shm_fd, protection, size)?;
+        let header = segment.header();
+        let capacity = header.capacity as usize;
+        if header.is_valid() && (2..=MAX_CAPACITY).contains(&capacity) && segment_size(capacity) == size {
+            segment.capacity = capacity;
+        }
+        Ok(segment)
+    }
+
+    fn map_with<F: AsFd>(shm_fd: F, protection: ProtFlags, len: usize) -> nix::Result<Self> {
+        let ptr = unsafe {
+            mman::mmap(
+                None,
+                NonZero::new(len).ok_or(Errno::EINVAL)?,
+                protection,
+                MapFlags::MAP_SHARED,
+                shm_fd,
+                0)?
+        };
+        Ok(Segment { ptr, len, capacity: 0 })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr() as *mut u8
+    }
+
+    pub fn header(&self) -> &Header {
+        unsafe { &*(self.as_ptr() as *const Header) }
+    }
+
+    /// Number of request slots in the ring.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn request_slot(&self, index: usize) -> &RequestSlot {
+        assert!(index < self.capacity);
+        unsafe {
+            let slots = self.as_ptr().add(size_of::<Header>()) as *const RequestSlot;
+            &*slots.add(index)
+        }
+    }
+
+    /// Reserves the slot at the write index for the calling process.
+    ///
+    /// The write lock is only held while the index is advanced. If the client
+    /// holding it died, the lock is taken over.
+    pub fn try_reserve(&self) -> Reservation<'_> {
+        let header = self.header();
+        let pid = std::process::id();
+        if let Err(owner) = header.write_lock.compare_exchange(0, pid, Ordering::SeqCst, Ordering::Acquire) {
+            if !process_alive(owner) {
+                let _ = header.write_lock.compare_exchange(owner, 0, Ordering::AcqRel, Ordering::Relaxed);
+            }
+            return Reservation::Locked;
+        }
+
+        let write_index = header.write_index.load(Ordering::Relaxed);
+        let next_write = (write_index + 1) % self.capacity;
+        // Pairs with `begin_shutdown`, which changes the state before checking the lock
+        let reservation = if header.state.load(Ordering::SeqCst) == STATE_SHUTTING_DOWN {
+            Reservation::Closed
+        } else if next_write == header.read_index.load(Ordering::Acquire) {
+            header.queue_full.fetch_add(1, Ordering::Relaxed);
+            Reservation::Full
+        } else {
+            let slot = self.request_slot(write_index);
+            slot.owner.store(pid, Ordering::Relaxed);
+            slot.state.store(REQUEST_RESERVED, Ordering::Relaxed);
+            header.write_index.store(next_write, Ordering::Release);
+            Reservation::Reserved(slot)
+        };
+
+        header.write_lock.store(0, Ordering::Release);
+        reservation
+    }
+
+    /// Takes the request at the read index off the ring. Only the server should call this.
+    pub fn try_dequeue(&self) -> Dequeued {
+        let header = self.header();
+        let read_index = header.read_index.load(Ordering::Relaxed);
+        if read_index == header.write_index.load(Ordering::Acquire) {
+            return Dequeued::Empty;
+        }
+
+        let slot = self.request_slot(read_index);
+        let dequeued = if slot.is_committed() {
+            Dequeued::Request(slot.read())
+        } else if process_alive(slot.owner()) {
+            // The client is still copying the request in
+            return Dequeued::Empty;
+        } else {
+            Dequeued::Skipped { owner: slot.owner() }
+        };
+
+        slot.state.store(REQUEST_EMPTY, Ordering::Relaxed);
+        header.read_index.store((read_index + 1) % self.capacity, Ordering::Release);
+        dequeued
+    }
+
+    pub fn reply_slot(&self, index: usize) -> Option<&ReplySlot> {
+        if index >= REPLY_SLOTS {
+            return None;
+        }
+        unsafe {
+            let slots = self.as_ptr()
+                .add(size_of::<Header>() + self.capacity * size_of::<RequestSlot>()) as *const ReplySlot;
+            Some(&*slots.add(index))
+        }
+    }
+
+    /// The registration table, holding the pid of the client in each slot (0 if free).
+    fn clients(&self) -> &[AtomicU32] {
+        unsafe {
+            let table = self.as_ptr().add(
+                size_of::<Header>()
+                    + self.capacity * size_of::<RequestSlot>()
+                    + REPLY_SLOTS * size_of::<ReplySlot>()) as *const AtomicU32;
+            std::slice::from_raw_parts(table, MAX_CLIENTS)
+        }
+    }
+
+    /// Clients holding a slot of the registration table, including ones that
+    /// died without unregistering.
+    pub fn registered_clients(&self) -> Vec<ClientId> {
+        self.clients()
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, entry)| match entry.load(Ordering::Acquire) {
+                0 => None,
+                pid => Some(ClientId { pid, slot: slot as u32 }),
+            })
+            .collect()
+    }
+
+    /// Registers the calling process in a free slot of the registration table.
+    /// Slots of clients that died without unregistering are reclaimed.
+    pub fn register_client(&self) -> Option<ClientId> {
+        let pid = std::process::id();
+        for (slot, entry) in self.clients().iter().enumerate() {
+            let current = entry.load(Ordering::Acquire);
+            if current != 0 && process_alive(current) {
+                continue;
+            }
+            if entry.compare_exchange(current, pid, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                return Some(ClientId { pid, slot: slot as u32 });
+            }
+        }
+        None
+    }
+
+    pub fn unregister_client(&self, id: ClientId) {
+        if let Some(entry) = self.clients().get(id.slot as usize) {
+            let _ = entry.compare_exchange(id.pid, 0, Ordering::AcqRel, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns whether a process with the given pid exists.
+pub fn process_alive(pid: u32) -> bool {
+    // kill() would address the whole process group for pid 0
+    if pid == 0 {
+        return false;
+    }
+    match signal::kill(Pid::from_raw(pid as i32), None) {
+        Ok(()) => true,
+        // The process exists but belongs to another user
+        Err(Errno::EPERM) => true,
+        Err(_) => false,
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = mman::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// Unit tests for the reply slots
+#[cfg(test)]
+fn empty_reply_slot() -> ReplySlot {
+    ReplySlot { word: AtomicU64::new(0), status: AtomicU8::new(0), request_id: AtomicU64::new(0), value: UnsafeCell::new([0; 256]) }
+}
+
+#[test]
+fn test_reply_slot_round_trip() {
+    let slot = empty_reply_slot();
+    assert!(slot.try_claim());
+    assert!(!slot.try_claim());
+    assert_eq!(slot.take(), None);
+    slot.complete(std::process::id(), 7, ReplyStatus::Ok, "value1");
+    assert_eq!(slot.take(), Some(Reply { request_id: 7, status: ReplyStatus::Ok, value: "value1".to_string() }));
+    assert!(slot.try_claim());
+}
+
+#[test]
+fn test_reply_slot_abandoned() {
+    let slot = empty_reply_slot();
+    assert!(slot.try_claim());
+    assert!(slot.abandon());
+    slot.complete(std::process::id(), 1, ReplyStatus::NotFound, "");
+    // The server frees abandoned slots once it has answered
+    assert!(slot.try_claim());
+}
+
+#[test]
+fn test_reply_slot_reclaimed_from_dead_owner() {
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let dead_pid = child.id();
+    child.wait().unwrap();
+
+    let slot = empty_reply_slot();
+    slot.word.store(slot_word(dead_pid, SLOT_PENDING), Ordering::Relaxed);
+    assert!(slot.try_claim());
+    // A late reply for the dead client must not reach the new owner
+    slot.complete(dead_pid, 1, ReplyStatus::Ok, "stale");
+    assert_eq!(slot.take(), None);
+    slot.complete(std::process::id(), 2, ReplyStatus::Ok, "fresh");
+    assert_eq!(slot.take().unwrap().value, "fresh");
+}
+
+// Unit tests for segments of another layout
+
+#[test]
+fn test_foreign_segment_truncated_only_without_live_owner() {
+    let name = format!("/ForeignLayoutTest{}", std::process::id());
+    let shm_fd = mman::shm_open(name.as_str(), OFlag::O_CREAT | OFlag::O_RDWR, Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+    ftruncate(shm_fd.as_fd(), 64).unwrap();
+    let file = File::from(shm_fd);
+    let mut prefix = [0u8; 16];
+    prefix[..8].copy_from_slice(&SEGMENT_MAGIC.to_ne_bytes());
+    prefix[12..].copy_from_slice(&std::process::id().to_ne_bytes());
+    file.write_all_at(&prefix, 0).unwrap();
+
+    // Its server is still running, so the segment is left as it is
+    let pid = std::process::id();
+    assert!(matches!(Segment::create(&name, false), Err(SegmentError::InUse { pid: owner }) if owner == pid));
+    assert_eq!(file.metadata().unwrap().len(), 64);
+
+    file.write_all_at(&0u32.to_ne_bytes(), 12).unwrap();
+    let (_segment, startup) = Segment::create(&name, false).unwrap();
+    assert!(matches!(startup, Startup::Reinitialized { previous_pid: 0 }));
+    assert_eq!(file.metadata().unwrap().len(), segment_size(DEFAULT_CAPACITY) as u64);
+    Segment::unlink(&name).unwrap();
+}
+
+// Unit tests for the ring capacity
+
+#[test]
+fn test_ring_capacity_from_header() {
+    let name = format!("/RingCapacityTest{}", std::process::id());
+    let (segment, startup) = Segment::create_with_capacity(&name, false, DEFAULT_SEGMENT_MODE, 3).unwrap();
+    assert_eq!(startup, Startup::Created);
+    let client = Segment::open(&name).unwrap();
+    assert_eq!(client.capacity(), 3);
+    // A ring of 3 slots holds 2 requests
+    assert!(matches!(client.try_reserve(), Reservation::Reserved(_)));
+    assert!(matches!(client.try_reserve(), Reservation::Reserved(_)));
+    assert!(matches!(client.try_reserve(), Reservation::Full));
+    assert_eq!(segment.header().pending(), 2);
+
+    // The next server with another capacity starts over with a ring of its size
+    segment.header().server_pid.store(0, Ordering::Release);
+    drop((segment, client));
+    let (segment, startup) = Segment::create_with_capacity(&name, true, DEFAULT_SEGMENT_MODE, 5).unwrap();
+    assert_eq!(startup, Startup::Reinitialized { previous_pid: 0 });
+    assert_eq!((segment.capacity(), segment.header().pending()), (5, 0));
+    assert_eq!(Segment::open(&name).unwrap().capacity(), 5);
+    Segment::unlink(&name).unwrap();
+}
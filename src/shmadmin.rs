@@ -0,0 +1,101 @@
+use shared_serve::admin::{SegmentDump, SegmentInfo, SegmentStatus};
+use shared_serve::{Segment, DEFAULT_SEGMENT_NAME};
+use clap::{Parser, Subcommand};
+use std::error::Error;
+
+/// Inspects the shared memory segments of shared_serve servers.
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header and the pending requests of a segment without changing it
+    Inspect {
+        /// Name of the shared memory segment
+        #[arg(long, default_value = DEFAULT_SEGMENT_NAME)]
+        name: String,
+        /// Include every slot of the ring, not just the pending ones
+        #[arg(long)]
+        all: bool,
+        /// Print the dump as JSON, e.g. to attach it to a bug report
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the segments in /dev/shm and remove the ones left behind by servers that are gone
+    Cleanup {
+        /// Only consider these segments instead of all of them
+        names: Vec<String>,
+        /// Report what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Remove the named segments even if a server is running or left requests for the next one
+        #[arg(long, requires = "names")]
+        force: bool,
+    },
+}
+
+fn inspect(name: &str, all: bool, json: bool) -> Result<(), Box<dyn Error>> {
+    let segment = Segment::open_read_only(name)
+        .map_err(|e| format!("Failed to open segment {}: {}", name, e))?;
+    let dump = SegmentDump::capture(name, &segment, all);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&dump)?);
+    } else {
+        println!("{}", dump);
+    }
+    Ok(())
+}
+
+fn cleanup(names: &[String], dry_run: bool, force: bool) -> Result<(), Box<dyn Error>> {
+    let mut failed = false;
+    let segments = if names.is_empty() {
+        SegmentInfo::find_all()?
+    } else {
+        names
+            .iter()
+            .filter_map(|name| {
+                let info = SegmentInfo::probe(name);
+                if info.is_none() {
+                    // Never touch objects of other programs, even with --force
+                    eprintln!("{}: not a shared_serve segment", name);
+                    failed = true;
+                }
+                info
+            })
+            .collect()
+    };
+
+    for segment in &segments {
+        // Preserved segments hold requests for the next server on purpose
+        let remove = force || segment.status == SegmentStatus::Orphaned;
+        let action = match (remove, dry_run) {
+            (false, _) => "kept".to_string(),
+            (true, true) => "would remove".to_string(),
+            (true, false) => match Segment::unlink(&segment.name) {
+                Ok(()) => "removed".to_string(),
+                Err(e) => {
+                    failed = true;
+                    format!("failed to remove: {}", e)
+                }
+            },
+        };
+        println!("{}, {}", segment, action);
+    }
+    if segments.is_empty() && names.is_empty() {
+        println!("No segments found");
+    }
+    if failed {
+        return Err("Some segments could not be cleaned up".into());
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    match Args::parse().command {
+        Command::Inspect { name, all, json } => inspect(&name, all, json),
+        Command::Cleanup { names, dry_run, force } => cleanup(&names, dry_run, force),
+    }
+}
@@ -0,0 +1,194 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Number of entries in `Stats::chain_lengths`. The last one counts all
+/// chains at least that long.
+pub const CHAIN_HISTOGRAM_LEN: usize = 5;
+
+/// Number of pages of the reply to a STATS request. The client asks for each
+/// of them, with the page number as the key.
+pub const STATS_PAGES: usize = 2;
+/// Most bytes of a reply value, which a page must fit into.
+const PAGE_LEN: usize = 256;
+
+/// Server counters returned by the STATS operation.
+///
+/// The server sends them as space separated `name=value` pairs, split over
+/// `STATS_PAGES` pages that each fit into the value of a reply.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub gets: u64,
+    pub inserts: u64,
+    pub deletes: u64,
+    pub stats: u64,
+    pub scans: u64,
+    /// GETs that found their key.
+    pub hits: u64,
+    /// GETs that didn't.
+    pub misses: u64,
+    /// Reservations rejected because the ring was full.
+    pub queue_full: u64,
+    pub queue_depth: u64,
+    pub keys: u64,
+    /// Bytes taken up by the stored keys and values.
+    pub memory_bytes: u64,
+    /// Number of buckets whose chain holds 0, 1, 2, 3 and 4 or more keys.
+    pub chain_lengths: [u64; CHAIN_HISTOGRAM_LEN],
+    pub workers: u64,
+    /// Workers processing a request right now.
+    pub busy_workers: u64,
+    /// Share of worker time spent processing requests since the server started, in percent.
+    pub utilization: f64,
+}
+
+impl Stats {
+    /// Sorts the chain length of every bucket into the histogram.
+    pub fn chain_histogram(chain_lengths: impl IntoIterator<Item = usize>) -> [u64; CHAIN_HISTOGRAM_LEN] {
+        let mut histogram = [0; CHAIN_HISTOGRAM_LEN];
+        for length in chain_lengths {
+            histogram[length.min(CHAIN_HISTOGRAM_LEN - 1)] += 1;
+        }
+        histogram
+    }
+
+    /// The wire format of one page of the reply to a STATS request, or `None`
+    /// past the last page. The counters are split over the pages so that each
+    /// fits into a reply even with every counter at `u64::MAX`.
+    pub fn encode_page(&self, page: usize) -> Option<String> {
+        let chains: Vec<String> = self.chain_lengths.iter().map(|count| count.to_string()).collect();
+        match page {
+            0 => Some(format!(
+                "get={} insert={} delete={} stats={} scan={} hits={} misses={} queue_full={} depth={}",
+                self.gets, self.inserts, self.deletes, self.stats, self.scans, self.hits, self.misses, self.queue_full, self.queue_depth,
+            )),
+            1 => Some(format!(
+                "keys={} bytes={} chains={} workers={} busy={} util={:.1}",
+                self.keys,
+                self.memory_bytes,
+                chains.join(","),
+                self.workers,
+                self.busy_workers,
+                // A percentage, clamped so that a bogus value can't overflow the page
+                self.utilization.clamp(0.0, 100.0),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Parses the pages of `encode_page`, joined by spaces. Unknown counters
+    /// are skipped, so older clients keep working when the server reports more
+    /// of them.
+    pub fn parse(encoded: &str) -> Option<Self> {
+        let mut stats = Stats::default();
+        for pair in encoded.split_whitespace() {
+            let (name, value) = pair.split_once('=')?;
+            match name {
+                "get" => stats.gets = value.parse().ok()?,
+                "insert" => stats.inserts = value.parse().ok()?,
+                "delete" => stats.deletes = value.parse().ok()?,
+                "stats" => stats.stats = value.parse().ok()?,
+                "scan" => stats.scans = value.parse().ok()?,
+                "hits" => stats.hits = value.parse().ok()?,
+                "misses" => stats.misses = value.parse().ok()?,
+                "queue_full" => stats.queue_full = value.parse().ok()?,
+                "depth" => stats.queue_depth = value.parse().ok()?,
+                "keys" => stats.keys = value.parse().ok()?,
+                "bytes" => stats.memory_bytes = value.parse().ok()?,
+                "chains" => {
+                    for (entry, count) in stats.chain_lengths.iter_mut().zip(value.split(',')) {
+                        *entry = count.parse().ok()?;
+                    }
+                }
+                "workers" => stats.workers = value.parse().ok()?,
+                "busy" => stats.busy_workers = value.parse().ok()?,
+                "util" => stats.utilization = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(stats)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Requests: GET {}, INSERT {}, DELETE {}, STATS {}, SCAN {}", self.gets, self.inserts, self.deletes, self.stats, self.scans)?;
+        writeln!(f, "Hits: {}, Misses: {}", self.hits, self.misses)?;
+        writeln!(f, "Queue depth: {}, Queue full rejections: {}", self.queue_depth, self.queue_full)?;
+        writeln!(f, "Keys: {}, Memory: {} bytes", self.keys, self.memory_bytes)?;
+        let chains: Vec<String> = self
+            .chain_lengths
+            .iter()
+            .enumerate()
+            .map(|(length, count)| {
+                let plus = if length == CHAIN_HISTOGRAM_LEN - 1 { "+" } else { "" };
+                format!("{}{}: {}", length, plus, count)
+            })
+            .collect();
+        writeln!(f, "Bucket chain lengths: {}", chains.join(", "))?;
+        write!(f, "Workers: {} busy of {}, Utilization: {:.1}%", self.busy_workers, self.workers, self.utilization)
+    }
+}
+
+// Unit tests for the stats wire format
+#[test]
+fn test_stats_round_trip() {
+    let stats = Stats {
+        gets: 10,
+        inserts: 5,
+        deletes: 2,
+        stats: 1,
+        scans: 6,
+        hits: 7,
+        misses: 3,
+        queue_full: 4,
+        queue_depth: 9,
+        keys: 3,
+        memory_bytes: 123,
+        chain_lengths: [1, 2, 0, 0, 1],
+        workers: 4,
+        busy_workers: 1,
+        utilization: 12.5,
+    };
+    let pages: Vec<String> = (0..STATS_PAGES).map(|page| stats.encode_page(page).unwrap()).collect();
+    assert_eq!(Stats::parse(&pages.join(" ")), Some(stats));
+    assert_eq!(Stats::default().encode_page(STATS_PAGES), None);
+}
+
+#[test]
+fn test_stats_pages_fit_into_reply() {
+    let stats = Stats {
+        gets: u64::MAX,
+        inserts: u64::MAX,
+        deletes: u64::MAX,
+        stats: u64::MAX,
+        scans: u64::MAX,
+        hits: u64::MAX,
+        misses: u64::MAX,
+        queue_full: u64::MAX,
+        queue_depth: u64::MAX,
+        keys: u64::MAX,
+        memory_bytes: u64::MAX,
+        chain_lengths: [u64::MAX; CHAIN_HISTOGRAM_LEN],
+        workers: u64::MAX,
+        busy_workers: u64::MAX,
+        utilization: f64::MAX,
+    };
+    for page in 0..STATS_PAGES {
+        let encoded = stats.encode_page(page).unwrap();
+        assert!(encoded.len() <= PAGE_LEN, "page {} is {} bytes long", page, encoded.len());
+    }
+    let pages: Vec<String> = (0..STATS_PAGES).map(|page| stats.encode_page(page).unwrap()).collect();
+    assert_eq!(Stats::parse(&pages.join(" ")), Some(Stats { utilization: 100.0, ..stats }));
+}
+
+#[test]
+fn test_stats_parse_skips_unknown_counters() {
+    let stats = Stats::parse("get=3 uptime=10").unwrap();
+    assert_eq!(stats.gets, 3);
+    assert_eq!(Stats::parse("get=three"), None);
+}
+
+#[test]
+fn test_chain_histogram() {
+    assert_eq!(Stats::chain_histogram([0, 1, 1, 3, 4, 9]), [1, 2, 0, 1, 2]);
+}
@@ -0,0 +1,437 @@
+use crate::segment::{Reply, ReplyStatus, MAX_CLIENTS};
+use crate::{ClientId, Operation, Request};
+use log::{debug, warn};
+use nix::errno::Errno;
+use nix::sys::socket::{recv, MsgFlags};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Longest frame body: a request with the longest key and value.
+pub const MAX_FRAME_LEN: usize = 1 + 8 + 1 + 64 + 256;
+
+/// Most requests of one connection the server reads ahead of their replies.
+/// Further requests stay in the socket until earlier ones are answered, so
+/// a client can't pile up requests faster than the workers apply them.
+pub const MAX_IN_FLIGHT: usize = 64;
+
+/// Replies of one connection waiting for its writer thread. A client that
+/// lets this many pile up has stopped reading, and its connection is closed.
+const REPLY_QUEUE_LEN: usize = MAX_IN_FLIGHT;
+
+/// How long the writer thread of a connection waits for the client to take
+/// a reply off its socket before it closes the connection.
+const REPLY_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the accepting thread tries to tell a connection over the limit
+/// that it is refused, so that it can't be held up by that client.
+const REFUSAL_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Frames sent over a socket are a big-endian u32 with the length of the body,
+// followed by the body:
+//
+// - hello, sent by the server once it accepted a connection:
+//   pid u32, slot u32 of the `ClientId` it assigned to the connection,
+//   or nothing if the server has too many connections and closes this one
+// - request: operation u8, request id u64, key length u8, key, value
+// - reply: request id u64, status u8, value
+//
+// Keys and values are UTF-8, the value takes up the rest of the body.
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Writes one frame at once, so frames of concurrent writers sharing a lock don't interleave.
+pub fn write_frame<W: Write>(writer: &mut W, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(body);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Reads the body of the next frame. Returns `None` if the peer closed the
+/// connection between frames.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read(&mut len[..1])? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut len[1..])?,
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid("frame too long"));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn encode_hello(id: ClientId) -> Vec<u8> {
+    let mut body = id.pid.to_be_bytes().to_vec();
+    body.extend_from_slice(&id.slot.to_be_bytes());
+    body
+}
+
+pub fn decode_hello(body: &[u8]) -> io::Result<ClientId> {
+    if body.len() != 8 {
+        return Err(invalid("malformed hello"));
+    }
+    Ok(ClientId {
+        pid: u32::from_be_bytes(body[..4].try_into().unwrap()),
+        slot: u32::from_be_bytes(body[4..].try_into().unwrap()),
+    })
+}
+
+pub fn encode_request(request: &Request) -> Vec<u8> {
+    let (key, value) = (request.key_str().as_bytes(), request.value_str().as_bytes());
+    let mut body = Vec::with_capacity(10 + key.len() + value.len());
+    body.push(request.operation as u8);
+    body.extend_from_slice(&request.request_id.to_be_bytes());
+    body.push(key.len() as u8);
+    body.extend_from_slice(key);
+    body.extend_from_slice(value);
+    body
+}
+
+/// Reads a request frame. The request id is set, the client and timestamp are left to the server.
+pub fn decode_request(body: &[u8]) -> io::Result<Request> {
+    if body.len() < 10 {
+        return Err(invalid("truncated request"));
+    }
+    let operation = match body[0] {
+        0 => Operation::GET,
+        1 => Operation::INSERT,
+        2 => Operation::DELETE,
+        3 => Operation::STATS,
+        4 => Operation::SCAN,
+        _ => return Err(invalid("unknown operation")),
+    };
+    let request_id = u64::from_be_bytes(body[1..9].try_into().unwrap());
+    let key_len = body[9] as usize;
+    if key_len > 64 || body.len() < 10 + key_len || body.len() > 10 + key_len + 256 {
+        return Err(invalid("key or value too long"));
+    }
+    let key = std::str::from_utf8(&body[10..10 + key_len]).map_err(|_| invalid("key is not UTF-8"))?;
+    let value = std::str::from_utf8(&body[10 + key_len..]).map_err(|_| invalid("value is not UTF-8"))?;
+    let mut request = Request::new(operation, key, value);
+    request.request_id = request_id;
+    Ok(request)
+}
+
+pub fn encode_reply(reply: &Reply) -> Vec<u8> {
+    let mut body = reply.request_id.to_be_bytes().to_vec();
+    body.push(reply.status as u8);
+    body.extend_from_slice(reply.value.as_bytes());
+    body
+}
+
+pub fn decode_reply(body: &[u8]) -> io::Result<Reply> {
+    if body.len() < 9 {
+        return Err(invalid("truncated reply"));
+    }
+    let status = match body[8] {
+        0 => ReplyStatus::Ok,
+        1 => ReplyStatus::NotFound,
+        2 => ReplyStatus::Full,
+        _ => return Err(invalid("unknown reply status")),
+    };
+    Ok(Reply {
+        request_id: u64::from_be_bytes(body[..8].try_into().unwrap()),
+        status,
+        value: String::from_utf8(body[9..].to_vec()).map_err(|_| invalid("value is not UTF-8"))?,
+    })
+}
+
+/// A connected Unix domain or TCP socket.
+pub trait Stream: Read + Write + AsRawFd + Send + Sync + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn try_clone_boxed(&self) -> io::Result<Box<dyn Stream>>;
+    /// Closes both directions, waking up a thread blocked reading the stream.
+    fn shutdown(&self);
+}
+
+macro_rules! impl_stream {
+    ($stream:ty) => {
+        impl Stream for $stream {
+            fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+                <$stream>::set_read_timeout(self, timeout)
+            }
+
+            fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+                <$stream>::set_write_timeout(self, timeout)
+            }
+
+            fn try_clone_boxed(&self) -> io::Result<Box<dyn Stream>> {
+                Ok(Box::new(self.try_clone()?))
+            }
+
+            fn shutdown(&self) {
+                let _ = <$stream>::shutdown(self, Shutdown::Both);
+            }
+        }
+    };
+}
+
+impl_stream!(UnixStream);
+impl_stream!(TcpStream);
+
+/// Whether the peer closed the stream, without blocking or consuming any data.
+/// Lets a client reconnect before sending a request the server would never read.
+pub fn peer_closed(fd: RawFd) -> bool {
+    match recv(fd, &mut [0u8; 1], MsgFlags::MSG_PEEK | MsgFlags::MSG_DONTWAIT) {
+        Ok(0) => true,
+        // Nothing to read, or a late reply to a request that timed out
+        Ok(_) | Err(Errno::EAGAIN) => false,
+        Err(_) => true,
+    }
+}
+
+/// Requests of one connection that were read but not answered yet.
+#[derive(Default)]
+struct InFlight {
+    count: Mutex<usize>,
+    answered: Condvar,
+}
+
+impl InFlight {
+    fn wait_below(&self, limit: usize) {
+        let mut count = self.count.lock().unwrap();
+        while *count >= limit {
+            count = self.answered.wait(count).unwrap();
+        }
+    }
+}
+
+/// Hands the reply to one request of a connection to the connection's
+/// writer thread. Dropping it, whether or not a reply was sent, lets the
+/// connection read a further request.
+pub struct Responder {
+    replies: SyncSender<Reply>,
+    stream: Arc<Box<dyn Stream>>,
+    in_flight: Arc<InFlight>,
+}
+
+impl Responder {
+    /// Queues the reply without waiting for the client to read it. If the
+    /// client has stopped taking replies and the queue is full, the
+    /// connection is closed.
+    pub fn send(&self, reply: &Reply) -> io::Result<()> {
+        match self.replies.try_send(reply.clone()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.stream.shutdown();
+                Err(io::Error::new(ErrorKind::WouldBlock, "client stopped taking replies"))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(ErrorKind::BrokenPipe, "connection closed")),
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        *self.in_flight.count.lock().unwrap() -= 1;
+        self.in_flight.answered.notify_one();
+    }
+}
+
+/// Limits on the connections accepted by one listener.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ConnectionLimits {
+    /// Most connections open at once, 0 for no limit.
+    pub max_connections: usize,
+    /// Close connections that sent no request for this long.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Numbers the connections of all listeners of the server.
+static NEXT_CONNECTION: AtomicU32 = AtomicU32::new(0);
+
+/// Accepts the connections from `incoming`, usually the `incoming()` of a
+/// listener, and hands each request read from them to `submit`, together
+/// with the responder of its connection.
+///
+/// Every connection is read by its own thread and is a client session of
+/// its own: its id is the server's pid and a slot past the registration
+/// slots of the segment, so it can't clash with a client of the segment.
+/// Clients may send further requests before the replies to earlier ones
+/// arrived, which are answered in order. Up to `MAX_IN_FLIGHT` of them are
+/// read ahead, then the connection is read again as replies go out. A connection that sends a
+/// malformed frame or stays idle for longer than the limit is closed.
+pub fn serve<S, I, F>(incoming: I, limits: ConnectionLimits, submit: F)
+where
+    S: Stream,
+    I: Iterator<Item = io::Result<S>>,
+    F: Fn(Request, Responder) + Clone + Send + 'static,
+{
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in incoming {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(error:% = e; "failed to accept connection");
+                continue;
+            }
+        };
+        if limits.max_connections > 0 && open.load(Ordering::Acquire) >= limits.max_connections {
+            warn!(max_connections = limits.max_connections; "refused connection over the limit");
+            let _ = stream.set_write_timeout(Some(REFUSAL_WRITE_TIMEOUT));
+            let _ = write_frame(&mut stream, &[]);
+            continue;
+        }
+        open.fetch_add(1, Ordering::AcqRel);
+        let id = ClientId { pid: std::process::id(), slot: MAX_CLIENTS as u32 + NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed) };
+        let (open, submit) = (open.clone(), submit.clone());
+        std::thread::spawn(move || {
+            debug!(client:% = id; "accepted connection");
+            match read_requests(stream, id, limits.idle_timeout, submit) {
+                Ok(()) => debug!(client:% = id; "connection closed"),
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    warn!(client:% = id, error:% = e; "closed connection after malformed frame")
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    debug!(client:% = id; "closed idle connection")
+                }
+                Err(e) => debug!(client:% = id, error:% = e; "connection lost"),
+            }
+            open.fetch_sub(1, Ordering::AcqRel);
+        });
+    }
+}
+
+fn read_requests<S: Stream, F: Fn(Request, Responder)>(stream: S, id: ClientId, idle_timeout: Option<Duration>, submit: F) -> io::Result<()> {
+    let mut writer = stream.try_clone_boxed()?;
+    writer.set_write_timeout(Some(REPLY_WRITE_TIMEOUT))?;
+    write_frame(&mut writer, &encode_hello(id))?;
+    let (replies, queued) = mpsc::sync_channel(REPLY_QUEUE_LEN);
+    std::thread::spawn(move || write_replies(writer, queued));
+
+    let control = Arc::new(stream.try_clone_boxed()?);
+    stream.set_read_timeout(idle_timeout)?;
+    let in_flight = Arc::new(InFlight::default());
+    // The connection closes once the replies to the requests in flight are written
+    let mut reader = BufReader::new(stream);
+    while let Some(body) = read_frame(&mut reader)? {
+        let mut request = decode_request(&body)?;
+        request.stamp(id, request.request_id);
+        *in_flight.count.lock().unwrap() += 1;
+        submit(request, Responder { replies: replies.clone(), stream: control.clone(), in_flight: in_flight.clone() });
+        in_flight.wait_below(MAX_IN_FLIGHT);
+    }
+    Ok(())
+}
+
+/// Writes the replies of a connection in the order they were queued, until
+/// every responder is gone or the client stops taking them.
+fn write_replies(mut stream: Box<dyn Stream>, queued: Receiver<Reply>) {
+    for reply in queued {
+        if write_frame(&mut stream, &encode_reply(&reply)).is_err() {
+            // Also wakes up the reading thread
+            stream.shutdown();
+            return;
+        }
+    }
+}
+
+// Unit tests for the socket framing
+#[test]
+fn test_frames_round_trip() {
+    let mut request = Request::new(Operation::INSERT, "kéy", &"v".repeat(256));
+    request.request_id = 7;
+    let reply = Reply { request_id: 7, status: ReplyStatus::Full, value: "value".to_string() };
+    let id = ClientId { pid: 42, slot: 300 };
+
+    let mut stream = Vec::new();
+    write_frame(&mut stream, &encode_hello(id)).unwrap();
+    write_frame(&mut stream, &encode_request(&request)).unwrap();
+    write_frame(&mut stream, &encode_reply(&reply)).unwrap();
+    let mut reader = stream.as_slice();
+    assert_eq!(decode_hello(&read_frame(&mut reader).unwrap().unwrap()).unwrap(), id);
+    let decoded = decode_request(&read_frame(&mut reader).unwrap().unwrap()).unwrap();
+    assert_eq!((decoded.operation, decoded.request_id), (Operation::INSERT, 7));
+    assert_eq!((decoded.key_str(), decoded.value_str()), (request.key_str(), request.value_str()));
+    assert_eq!(decode_reply(&read_frame(&mut reader).unwrap().unwrap()).unwrap(), reply);
+    assert!(read_frame(&mut reader).unwrap().is_none());
+}
+
+#[test]
+fn test_malformed_frames() {
+    let too_long = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+    assert_eq!(read_frame(&mut too_long.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+    // The connection closed in the middle of a frame
+    assert_eq!(read_frame(&mut [0u8, 0, 0, 5, 1].as_slice()).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    let request = encode_request(&Request::new(Operation::GET, "key", ""));
+    assert!(decode_request(&request).is_ok());
+    assert!(decode_request(&request[..9]).is_err());
+    let mut unknown = request.clone();
+    unknown[0] = 9;
+    assert!(decode_request(&unknown).is_err());
+    let mut long_key = request.clone();
+    long_key[9] = 65;
+    assert!(decode_request(&long_key).is_err());
+    let mut not_utf8 = request;
+    not_utf8[10] = 0xff;
+    assert!(decode_request(&not_utf8).is_err());
+    assert!(decode_reply(&[0; 8]).is_err());
+    assert!(decode_hello(&[0; 7]).is_err());
+}
+
+#[test]
+fn test_connection_reads_ahead_up_to_limit() {
+    let (server, mut client) = UnixStream::pair().unwrap();
+    let held = Arc::new(Mutex::new(Vec::new()));
+    let submitted = held.clone();
+    let reader = std::thread::spawn(move || {
+        read_requests(server, ClientId::default(), None, move |_, responder| submitted.lock().unwrap().push(responder))
+    });
+    assert!(read_frame(&mut client).unwrap().is_some());
+    for _ in 0..MAX_IN_FLIGHT + 10 {
+        write_frame(&mut client, &encode_request(&Request::new(Operation::GET, "key", ""))).unwrap();
+    }
+    while held.lock().unwrap().len() < MAX_IN_FLIGHT {
+        std::thread::yield_now();
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(held.lock().unwrap().len(), MAX_IN_FLIGHT);
+
+    // Answering requests lets the connection read the rest
+    let answered: Vec<Responder> = held.lock().unwrap().drain(..).collect();
+    drop(answered);
+    while held.lock().unwrap().len() < 10 {
+        std::thread::yield_now();
+    }
+    drop(client);
+    held.lock().unwrap().clear();
+    reader.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_client_not_reading_replies_is_closed() {
+    let (server, mut client) = UnixStream::pair().unwrap();
+    let refused = Arc::new(Mutex::new(None));
+    let outcome = refused.clone();
+    let reader = std::thread::spawn(move || {
+        read_requests(server, ClientId::default(), None, move |request, responder| {
+            let reply = Reply { request_id: request.request_id, status: ReplyStatus::Ok, value: "v".repeat(256) };
+            // Never waits for the client, even once its socket is full
+            let started = std::time::Instant::now();
+            if let Err(e) = responder.send(&reply) {
+                outcome.lock().unwrap().get_or_insert(e.kind());
+            }
+            assert!(started.elapsed() < Duration::from_secs(1));
+        })
+    });
+    assert!(read_frame(&mut client).unwrap().is_some());
+    let request = encode_request(&Request::new(Operation::GET, "key", ""));
+    while write_frame(&mut client, &request).is_ok() && refused.lock().unwrap().is_none() {}
+    let _ = reader.join().unwrap();
+    assert_eq!(*refused.lock().unwrap(), Some(ErrorKind::WouldBlock));
+}
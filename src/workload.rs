@@ -0,0 +1,411 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// SplitMix64, small and fast, which is all picking keys and operations needs.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n).
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+/// What a benchmark client does in one step, modelled after YCSB.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// GET of an existing key.
+    Read,
+    /// INSERT of an existing key.
+    Update,
+    /// INSERT of a new key.
+    Insert,
+    /// GETs of a run of consecutive keys. The server has no range queries, so
+    /// this is as close as a client can get to YCSB's scans.
+    Scan,
+    /// GET of a key followed by an INSERT of the same key.
+    ReadModifyWrite,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [Action::Read, Action::Update, Action::Insert, Action::Scan, Action::ReadModifyWrite];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Read => "read",
+            Action::Update => "update",
+            Action::Insert => "insert",
+            Action::Scan => "scan",
+            Action::ReadModifyWrite => "read-modify-write",
+        }
+    }
+}
+
+/// Proportions of the actions, which don't need to add up to anything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mix {
+    pub read: f64,
+    pub update: f64,
+    pub insert: f64,
+    pub scan: f64,
+    pub read_modify_write: f64,
+}
+
+impl Mix {
+    fn weights(&self) -> [f64; 5] {
+        [self.read, self.update, self.insert, self.scan, self.read_modify_write]
+    }
+
+    pub fn total(&self) -> f64 {
+        self.weights().iter().sum()
+    }
+
+    pub fn pick(&self, rng: &mut Rng) -> Action {
+        let mut point = rng.next_f64() * self.total();
+        for (action, weight) in Action::ALL.into_iter().zip(self.weights()) {
+            if point < weight {
+                return action;
+            }
+            point -= weight;
+        }
+        // Rounding may leave the point past the last weight
+        Action::ALL.into_iter().zip(self.weights()).rev().find(|(_, weight)| *weight > 0.0).map_or(Action::Read, |(action, _)| action)
+    }
+}
+
+impl fmt::Display for Mix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let parts: Vec<String> = Action::ALL
+            .into_iter()
+            .zip(self.weights())
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(action, weight)| format!("{} {:.0}%", action.name(), weight * 100.0 / total))
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// How keys are picked from the ones stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyDistribution {
+    Uniform,
+    /// A few keys are hot, scattered over the key space like YCSB's scrambled zipfian.
+    Zipfian,
+    /// Recently inserted keys are hot.
+    Latest,
+}
+
+impl FromStr for KeyDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(KeyDistribution::Uniform),
+            "zipfian" => Ok(KeyDistribution::Zipfian),
+            "latest" => Ok(KeyDistribution::Latest),
+            _ => Err(format!("unknown key distribution `{}`, expected `uniform`, `zipfian` or `latest`", s)),
+        }
+    }
+}
+
+impl fmt::Display for KeyDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyDistribution::Uniform => write!(f, "uniform"),
+            KeyDistribution::Zipfian => write!(f, "zipfian"),
+            KeyDistribution::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+/// The core workloads of YCSB.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Preset {
+    /// Update heavy: 50% reads, 50% updates.
+    A,
+    /// Read mostly: 95% reads, 5% updates.
+    B,
+    /// Read only.
+    C,
+    /// Read latest: 95% reads of recently inserted keys, 5% inserts.
+    D,
+    /// Short ranges: 95% scans, 5% inserts.
+    E,
+    /// Read-modify-write: 50% reads, 50% read-modify-writes.
+    F,
+}
+
+impl Preset {
+    pub fn mix(self) -> Mix {
+        match self {
+            Preset::A => Mix { read: 0.5, update: 0.5, ..Mix::default() },
+            Preset::B => Mix { read: 0.95, update: 0.05, ..Mix::default() },
+            Preset::C => Mix { read: 1.0, ..Mix::default() },
+            Preset::D => Mix { read: 0.95, insert: 0.05, ..Mix::default() },
+            Preset::E => Mix { scan: 0.95, insert: 0.05, ..Mix::default() },
+            Preset::F => Mix { read: 0.5, read_modify_write: 0.5, ..Mix::default() },
+        }
+    }
+
+    pub fn distribution(self) -> KeyDistribution {
+        match self {
+            Preset::D => KeyDistribution::Latest,
+            _ => KeyDistribution::Zipfian,
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Ok(Preset::A),
+            "b" => Ok(Preset::B),
+            "c" => Ok(Preset::C),
+            "d" => Ok(Preset::D),
+            "e" => Ok(Preset::E),
+            "f" => Ok(Preset::F),
+            _ => Err(format!("unknown workload `{}`, expected one of a to f", s)),
+        }
+    }
+}
+
+/// Zipfian distributed ranks in [0, items), with rank 0 the most popular.
+/// See Gray et al., "Quickly Generating Billion-Record Synthetic Databases".
+pub struct Zipfian {
+    items: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    /// The skew YCSB uses.
+    pub const THETA: f64 = 0.99;
+
+    pub fn new(items: u64) -> Self {
+        let theta = Self::THETA;
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zetan = zeta(items);
+        let eta = (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zetan);
+        Zipfian { items, theta, alpha: 1.0 / (1.0 - theta), zetan, eta }
+    }
+
+    pub fn next(&self, rng: &mut Rng) -> u64 {
+        let u = rng.next_f64();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.items - 1);
+        }
+        let rank = (self.items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        rank.min(self.items - 1)
+    }
+}
+
+/// Picks the keys to work on.
+pub struct KeyChooser {
+    distribution: KeyDistribution,
+    zipfian: Zipfian,
+}
+
+impl KeyChooser {
+    /// `records` is the number of keys loaded before the run.
+    pub fn new(distribution: KeyDistribution, records: u64) -> Self {
+        KeyChooser { distribution, zipfian: Zipfian::new(records.max(1)) }
+    }
+
+    /// The position of the next key among the `stored` ones, where the keys
+    /// inserted during the run follow the loaded ones.
+    pub fn next(&self, rng: &mut Rng, stored: u64) -> u64 {
+        match self.distribution {
+            KeyDistribution::Uniform => rng.below(stored),
+            // Scrambling spreads the hot keys over the buckets instead of
+            // piling them up in the first ones
+            KeyDistribution::Zipfian => fnv1a(self.zipfian.next(rng)) % stored,
+            KeyDistribution::Latest => stored - 1 - self.zipfian.next(rng).min(stored - 1),
+        }
+    }
+}
+
+fn fnv1a(value: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in value.to_le_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// The key stored for an index, `user` followed by the zero padded index.
+pub fn key_name(index: u64, key_size: usize) -> String {
+    format!("user{:0width$}", index, width = key_size.saturating_sub(4))
+}
+
+/// Buckets below this are one microsecond wide.
+const LINEAR_BUCKETS: usize = 64;
+/// Buckets per power of two above that, which keeps the error around 3%.
+const SUB_BUCKETS: usize = 32;
+const RECORDER_BUCKETS: usize = LINEAR_BUCKETS + (64 - 6) * SUB_BUCKETS;
+
+/// A latency histogram with a bounded relative error, for percentiles.
+///
+/// Unlike `metrics::LatencyHistogram`, it is owned by a single thread and
+/// can be merged with the recorders of other threads and processes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyRecorder {
+    counts: Vec<u64>,
+    count: u64,
+    sum_us: u64,
+    max_us: u64,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        LatencyRecorder { counts: vec![0; RECORDER_BUCKETS], count: 0, sum_us: 0, max_us: 0 }
+    }
+}
+
+impl LatencyRecorder {
+    fn bucket(us: u64) -> usize {
+        if us < LINEAR_BUCKETS as u64 {
+            return us as usize;
+        }
+        let exponent = 63 - us.leading_zeros() as usize;
+        let mantissa = (us >> (exponent - 5)) as usize;
+        LINEAR_BUCKETS + (exponent - 6) * SUB_BUCKETS + mantissa - SUB_BUCKETS
+    }
+
+    /// The largest latency in a bucket, in microseconds.
+    fn upper_bound(bucket: usize) -> u64 {
+        if bucket < LINEAR_BUCKETS {
+            return bucket as u64;
+        }
+        let exponent = (bucket - LINEAR_BUCKETS) / SUB_BUCKETS + 6;
+        let mantissa = ((bucket - LINEAR_BUCKETS) % SUB_BUCKETS + SUB_BUCKETS) as u64;
+        ((mantissa + 1) << (exponent - 5)) - 1
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[Self::bucket(us)] += 1;
+        self.count += 1;
+        self.sum_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum_us += other.sum_us;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.sum_us.checked_div(self.count).unwrap_or(0))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_us)
+    }
+
+    /// The latency below which `quantile` of the observations fall, e.g. 0.99.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(Self::upper_bound(bucket).min(self.max_us));
+            }
+        }
+        self.max()
+    }
+}
+
+// Unit tests for the benchmark workloads
+#[test]
+fn test_mix_follows_proportions() {
+    let mut rng = Rng::new(1);
+    let mix = Preset::B.mix();
+    let reads = (0..10_000).filter(|_| mix.pick(&mut rng) == Action::Read).count();
+    assert!((9_300..9_700).contains(&reads), "{} reads", reads);
+    assert_eq!(Preset::F.mix().to_string(), "read 50%, read-modify-write 50%");
+}
+
+#[test]
+fn test_zipfian_is_skewed() {
+    let mut rng = Rng::new(7);
+    let zipfian = Zipfian::new(1000);
+    let mut counts = vec![0u32; 1000];
+    for _ in 0..100_000 {
+        counts[zipfian.next(&mut rng) as usize] += 1;
+    }
+    assert!(counts[0] > counts[1] && counts[1] > counts[10] && counts[10] > counts[500]);
+    // The most popular key gets far more than its uniform share of 100
+    assert!(counts[0] > 5_000, "{}", counts[0]);
+}
+
+#[test]
+fn test_latest_prefers_new_keys() {
+    let mut rng = Rng::new(3);
+    let chooser = KeyChooser::new(KeyDistribution::Latest, 100);
+    let picks: Vec<u64> = (0..1000).map(|_| chooser.next(&mut rng, 150)).collect();
+    assert!(picks.iter().all(|&pick| pick < 150));
+    assert!(picks.iter().filter(|&&pick| pick == 149).count() > 100);
+}
+
+#[test]
+fn test_latency_recorder_percentiles() {
+    let mut recorder = LatencyRecorder::default();
+    for us in 1..=1000 {
+        recorder.record(Duration::from_micros(us));
+    }
+    let mut other = LatencyRecorder::default();
+    other.record(Duration::from_millis(50));
+    recorder.merge(&other);
+
+    assert_eq!(recorder.count(), 1001);
+    assert_eq!(recorder.percentile(0.0), Duration::from_micros(1));
+    let p50 = recorder.percentile(0.5).as_micros() as f64;
+    assert!((500.0..=500.0 * 1.04).contains(&p50), "p50 {}", p50);
+    let p99 = recorder.percentile(0.99).as_micros() as f64;
+    assert!((990.0..=990.0 * 1.04).contains(&p99), "p99 {}", p99);
+    assert_eq!(recorder.percentile(1.0), Duration::from_millis(50));
+    for us in [0, 63, 64, 65, 1000, 123_456, u64::MAX / 2] {
+        assert!(LatencyRecorder::upper_bound(LatencyRecorder::bucket(us)) >= us);
+    }
+}
@@ -0,0 +1,129 @@
+use std::process::Command;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use shared_serve::{AsyncClient, ClientConfig, ClientError, Segment, REPLY_SLOTS};
+mod common;
+
+const SEGMENT_NAME: &str = "AsyncClientTestQueue";
+const REQUEST_COUNT: usize = 2000;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_client_concurrent_requests() {
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    let client = Arc::new(AsyncClient::connect(SEGMENT_NAME).expect("Failed to connect to server"));
+
+    // Far more requests are submitted at once than there are reply slots
+    let inserts: Vec<_> = (0..REQUEST_COUNT).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move { client.insert(&format!("key{}", i), &format!("value{}", i)).await })
+    }).collect();
+    for insert in inserts {
+        insert.await.unwrap().expect("Insert failed");
+    }
+
+    let gets: Vec<_> = (0..REQUEST_COUNT).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move { (i, client.get(&format!("key{}", i)).await) })
+    }).collect();
+    for get in gets {
+        let (i, value) = get.await.unwrap();
+        assert_eq!(value.expect("Get failed"), Some(format!("value{}", i)));
+    }
+
+    assert!(client.delete("key0").await.unwrap());
+    assert_eq!(client.get("key0").await.unwrap(), None);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[tokio::test]
+async fn test_async_client_waits_for_reply_slot() {
+    const SEGMENT_NAME: &str = "AsyncClientReplySlotTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let config = ClientConfig { lock_timeout: Duration::from_millis(200), ..ClientConfig::default() };
+    let client = AsyncClient::connect_with(SEGMENT_NAME, config).expect("Failed to connect to server");
+
+    // Other clients hold every reply slot, while the queue has room
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    for index in 0..REPLY_SLOTS {
+        assert!(segment.reply_slot(index).unwrap().try_claim());
+    }
+    assert!(matches!(client.get("key").await, Err(ClientError::NoReplySlot)));
+
+    for index in 0..REPLY_SLOTS {
+        segment.reply_slot(index).unwrap().release();
+    }
+    assert_eq!(client.get("key").await.unwrap(), None);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[tokio::test]
+async fn test_async_client_times_out_on_write_lock() {
+    const SEGMENT_NAME: &str = "AsyncClientWriteLockTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let config = ClientConfig { lock_timeout: Duration::from_millis(200), ..ClientConfig::default() };
+    let client = AsyncClient::connect_with(SEGMENT_NAME, config).expect("Failed to connect to server");
+
+    // A live client holds the write lock, while the queue has room
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    segment.header().write_lock.store(std::process::id(), Ordering::SeqCst);
+    assert!(matches!(client.get("key").await, Err(ClientError::LockTimeout)));
+
+    segment.header().write_lock.store(0, Ordering::SeqCst);
+    assert_eq!(client.get("key").await.unwrap(), None);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_client_in_flight_limited_by_reply_slots() {
+    const SEGMENT_NAME: &str = "AsyncClientInFlightTestQueue";
+    const EXTRA: usize = 16;
+    // A ring with room for more requests than there are reply slots
+    let mut server = Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--name", SEGMENT_NAME, "--log-level", "warn"])
+        .env("SHARED_SERVE_SEGMENT_CAPACITY", (2 * REPLY_SLOTS).to_string())
+        .spawn()
+        .expect("Failed to start server");
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let client = Arc::new(AsyncClient::connect(SEGMENT_NAME).expect("Failed to connect to server"));
+
+    // While the server is stopped, requests pile up in the ring until the reply slots run out
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGSTOP).unwrap();
+    let gets: Vec<_> = (0..REPLY_SLOTS + EXTRA).map(|i| {
+        let client = client.clone();
+        tokio::spawn(async move { client.get(&format!("key{}", i)).await })
+    }).collect();
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while segment.header().pending() < REPLY_SLOTS {
+        assert!(Instant::now() < deadline, "Requests were not enqueued");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(segment.header().pending(), REPLY_SLOTS);
+
+    // The held back requests go out as the replies free their slots
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGCONT).unwrap();
+    for get in gets {
+        assert_eq!(get.await.unwrap().expect("Get failed"), None);
+    }
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
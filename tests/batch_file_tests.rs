@@ -0,0 +1,89 @@
+use serde_json::Value;
+use shared_serve::Client;
+use std::fs;
+use std::process::{Command, Output};
+use std::time::Duration;
+mod common;
+
+fn client(name: &str, args: &[&str]) -> Output {
+    Command::new("cargo")
+        .args(["run", "--bin", "client", "--", "--name", name])
+        .args(args)
+        .output()
+        .expect("Failed to run client")
+}
+
+fn stop(mut server: std::process::Child, client: Client) {
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_run_file_and_export_import() {
+    const SEGMENT_NAME: &str = "BatchFileTestQueue";
+    let directory = std::env::temp_dir().join(format!("batch_file_test_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let requests = directory.join("requests.txt");
+    let export = directory.join("export.jsonl");
+
+    let server = common::start_quiet_server_named(SEGMENT_NAME);
+    let connection = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    fs::write(
+        &requests,
+        [
+            r#"INSERT plain value"#,
+            r#"{"op":"INSERT","key":"json key","value":"json \"value\""}"#,
+            r#"INSERT 'with space' "line\nbreak""#,
+            r#""#,
+            r#"{"op":"GET","key":"json key"}"#,
+            r#"GET missing"#,
+            r#"DELETE"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+
+    let output = client(SEGMENT_NAME, &["--output", "json", "batch", requests.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2), "The DELETE without key must fail the run");
+    let results: Vec<Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let statuses: Vec<&str> = results.iter().map(|result| result["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, ["ok", "ok", "ok", "ok", "not_found", "error"]);
+    assert_eq!(results[3]["value"], "json \"value\"");
+    assert_eq!(results[5]["error"], "Line 7: DELETE requires key");
+
+    // Enough keys for the listing to take several pages
+    for i in 0..40 {
+        connection.insert(&format!("key_{:02}_{}", i, "x".repeat(40)), &i.to_string()).unwrap();
+    }
+    let mut keys = connection.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys.len(), 43);
+
+    let output = client(SEGMENT_NAME, &["export", export.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Exported 43 keys"));
+    stop(server, connection);
+
+    // A new server starts empty and gets everything back from the export
+    let server = common::start_quiet_server_named(SEGMENT_NAME);
+    let connection = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    assert!(connection.keys().unwrap().is_empty());
+    let output = client(SEGMENT_NAME, &["import", export.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    let mut imported = connection.keys().unwrap();
+    imported.sort();
+    assert_eq!(imported, keys);
+    assert_eq!(connection.get("with space").unwrap().as_deref(), Some("line\nbreak"));
+    assert_eq!(connection.get(&format!("key_07_{}", "x".repeat(40))).unwrap().as_deref(), Some("7"));
+
+    let output = client(SEGMENT_NAME, &["keys"]);
+    assert!(String::from_utf8_lossy(&output.stdout).lines().any(|line| line == "\"json key\""));
+
+    stop(server, connection);
+    fs::remove_dir_all(&directory).unwrap();
+}
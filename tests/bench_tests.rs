@@ -0,0 +1,40 @@
+use std::process::Command;
+use std::time::Duration;
+mod common;
+
+#[test]
+fn test_bench_reports_latencies() {
+    const SEGMENT_NAME: &str = "BenchTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "bench", "--", "--name", SEGMENT_NAME])
+        .args(["--workload", "f", "--records", "50", "--operations", "100", "--threads", "2", "--processes", "2"])
+        .output()
+        .expect("Failed to run bench");
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "bench failed: {}{}", report, String::from_utf8_lossy(&output.stderr));
+
+    assert!(report.contains("Workload: F (read 50%, read-modify-write 50%), zipfian keys"), "{}", report);
+    assert!(report.contains("Loaded 50 records"), "{}", report);
+    // 2 processes with 2 threads each
+    assert!(report.contains("Ran 400 operations"), "{}", report);
+    assert!(report.contains("with 4 clients"), "{}", report);
+    for operation in ["read", "read-modify-write"] {
+        let row = report
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some(operation))
+            .unwrap_or_else(|| panic!("No row for {}: {}", operation, report));
+        let errors: u64 = row.split_whitespace().last().unwrap().parse().unwrap();
+        assert_eq!(errors, 0, "{}", row);
+    }
+
+    let stats = client.stats().unwrap();
+    assert_eq!(stats.keys, 50);
+    assert!(stats.gets >= 400, "{}", stats);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
@@ -0,0 +1,68 @@
+use std::thread;
+use std::time::Duration;
+use shared_serve::{Client, ClientConfig, ClientError};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+mod common;
+
+const SEGMENT_NAME: &str = "ClientApiTestQueue";
+
+#[test]
+fn test_client_api_round_trip() {
+    let mut server = common::start_server_named(SEGMENT_NAME);
+    thread::sleep(Duration::from_secs(2));
+
+    let client = Client::connect(SEGMENT_NAME).expect("Failed to connect to server");
+    client.insert("api_key", "api_value").unwrap();
+    assert_eq!(client.get("api_key").unwrap(), Some("api_value".to_string()));
+    assert!(client.delete("api_key").unwrap());
+    assert_eq!(client.get("api_key").unwrap(), None);
+    assert!(!client.delete("api_key").unwrap());
+
+    // Every client gets its own registration slot
+    let other_client = Client::connect(SEGMENT_NAME).expect("Failed to connect to server");
+    assert_ne!(client.id(), other_client.id());
+    other_client.insert("other_key", "other_value").unwrap();
+
+    let client_id = client.id().to_string();
+    let other_client_id = other_client.id().to_string();
+    let expected_output = [
+        format!("processed request client={} request_id=1 operation=INSERT", client_id),
+        format!("processed request client={} request_id=5 operation=DELETE", client_id),
+        format!("processed request client={} request_id=1 operation=INSERT", other_client_id),
+    ];
+    common::check_server_output(
+        server.stderr.take().unwrap(),
+        expected_output.iter().map(String::as_str).collect(),
+        Duration::from_secs(10));
+    drop(client);
+    drop(other_client);
+
+    // Connecting fails once the server has removed the segment
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(matches!(Client::connect(SEGMENT_NAME), Err(ClientError::Connect(_))));
+}
+
+#[test]
+fn test_client_api_reply_timeout() {
+    let name = "ClientApiTimeoutQueue";
+    let mut server = common::start_server_named(name);
+    thread::sleep(Duration::from_secs(2));
+
+    let config = ClientConfig { reply_timeout: Duration::from_millis(200), ..ClientConfig::default() };
+    let client = Client::connect_with(name, config).expect("Failed to connect to server");
+
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGSTOP)
+        .expect("Failed to send SIGSTOP to server");
+    assert!(matches!(client.get("missing"), Err(ClientError::ReplyTimeout)));
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGCONT)
+        .expect("Failed to send SIGCONT to server");
+
+    // The abandoned slot doesn't prevent later requests from being answered
+    client.insert("after_timeout", "value").unwrap();
+    assert_eq!(client.get("after_timeout").unwrap(), Some("value".to_string()));
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
@@ -0,0 +1,111 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+mod common;
+
+fn client_command(name: &str, args: &[&str]) -> Command {
+    let mut command = Command::new("cargo");
+    command.args(["run", "--bin", "client", "--", "--name", name, "--output", "json"]).args(args);
+    command
+}
+
+/// Runs one client command and returns its exit status and the JSON object it printed.
+fn client_json(name: &str, args: &[&str]) -> (i32, Value) {
+    let output = client_command(name, args).output().expect("Failed to run client");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let value = serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("{}: {}", e, stdout));
+    (output.status.code().unwrap(), value)
+}
+
+#[test]
+fn test_json_output() {
+    const SEGMENT_NAME: &str = "ClientCliJsonTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    assert_eq!(
+        client_json(SEGMENT_NAME, &["insert", "json_key", "json_value"]),
+        (0, json!({ "operation": "insert", "key": "json_key", "status": "ok" }))
+    );
+    assert_eq!(
+        client_json(SEGMENT_NAME, &["get", "json_key"]),
+        (0, json!({ "operation": "get", "key": "json_key", "status": "ok", "value": "json_value" }))
+    );
+    assert_eq!(
+        client_json(SEGMENT_NAME, &["get", "other_key"]),
+        (1, json!({ "operation": "get", "key": "other_key", "status": "not_found" }))
+    );
+    let (status, stats) = client_json(SEGMENT_NAME, &["stats"]);
+    assert_eq!(status, 0);
+    assert_eq!(stats["status"], "ok");
+    assert_eq!(stats["stats"]["keys"], 1);
+    assert_eq!(stats["stats"]["gets"], 2);
+
+    let mut batch = client_command(SEGMENT_NAME, &["batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start client");
+    let mut stdin = batch.stdin.take().unwrap();
+    writeln!(stdin, "DELETE json_key").unwrap();
+    writeln!(stdin, "GET").unwrap();
+    writeln!(stdin, "DELETE json_key").unwrap();
+    writeln!(stdin, "INSERT 'two words' \"hello \\\"world\\\"\"").unwrap();
+    // "two words" in hex
+    writeln!(stdin, "GET hex:74776f20776f726473").unwrap();
+    writeln!(stdin, "GET \"unterminated").unwrap();
+    drop(stdin);
+    let output = batch.wait_with_output().unwrap();
+    // Some of the lines were invalid
+    assert_eq!(output.status.code(), Some(2));
+    let lines: Vec<Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            json!({ "operation": "delete", "key": "json_key", "status": "ok" }),
+            json!({ "status": "error", "error": "GET requires key" }),
+            json!({ "operation": "delete", "key": "json_key", "status": "not_found" }),
+            json!({ "operation": "insert", "key": "two words", "status": "ok" }),
+            json!({ "operation": "get", "key": "two words", "status": "ok", "value": "hello \"world\"" }),
+            json!({ "status": "error", "error": "Missing closing quote \"" }),
+        ]
+    );
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+
+    let (status, error) = client_json(SEGMENT_NAME, &["get", "json_key"]);
+    assert_eq!(status, 2);
+    assert_eq!(error["status"], "error");
+}
+
+#[test]
+fn test_subscribe_prints_changes() {
+    const SEGMENT_NAME: &str = "ClientCliSubscribeTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    client.insert("watched", "first").unwrap();
+
+    let mut subscriber = client_command(SEGMENT_NAME, &["subscribe", "watched", "--interval", "10", "--count", "3"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start client");
+    let mut lines = BufReader::new(subscriber.stdout.take().unwrap()).lines();
+    let mut next_value = || serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap()["value"].clone();
+
+    assert_eq!(next_value(), "first");
+    client.insert("watched", "second").unwrap();
+    assert_eq!(next_value(), "second");
+    client.delete("watched").unwrap();
+    assert_eq!(next_value(), Value::Null);
+    assert!(subscriber.wait().unwrap().success());
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
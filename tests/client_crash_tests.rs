@@ -0,0 +1,62 @@
+use std::env;
+use std::process::Command;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use shared_serve::{Reservation, Segment};
+mod common;
+
+const SEGMENT_NAME: &str = "ClientCrashTestQueue";
+const CRASH_MODE_VAR: &str = "SHARED_SERVE_CRASH_MODE";
+
+/// Runs in a child process and exits the way a client crashing in the middle of an enqueue would.
+#[test]
+#[ignore]
+fn crashing_client() {
+    let Ok(mode) = env::var(CRASH_MODE_VAR) else {
+        return;
+    };
+    let segment = Segment::open(SEGMENT_NAME).expect("Failed to open segment");
+    match mode.as_str() {
+        // Dies while holding the write lock
+        "holding_lock" => segment.header().write_lock.store(std::process::id(), Ordering::Release),
+        // Dies after reserving a slot but before the request was committed
+        "torn_slot" => match segment.try_reserve() {
+            Reservation::Reserved(_) => {},
+            _ => panic!("Failed to reserve a slot"),
+        },
+        _ => panic!("Unknown crash mode {}", mode),
+    }
+    std::process::exit(1);
+}
+
+fn crash_client(mode: &str) {
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["crashing_client", "--exact", "--ignored", "--nocapture"])
+        .env(CRASH_MODE_VAR, mode)
+        .status()
+        .expect("Failed to run crashing client");
+    assert_eq!(status.code(), Some(1), "Crashing client did not exit as expected");
+}
+
+#[test]
+fn test_recovery_from_client_crash_mid_enqueue() {
+    let server = common::start_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    crash_client("holding_lock");
+    // The lock of the dead client is taken over instead of blocking everyone
+    client.insert("after_lock_crash", "value1").expect("Insert after lock crash failed");
+
+    crash_client("torn_slot");
+    // The server skips the uncommitted slot and keeps serving
+    client.insert("after_torn_slot", "value2").expect("Insert after torn slot failed");
+    assert_eq!(client.get("after_lock_crash").unwrap(), Some("value1".to_string()));
+    assert_eq!(client.get("after_torn_slot").unwrap(), Some("value2".to_string()));
+    drop(client);
+
+    common::stop_server_with_sigint(&server);
+    let output = server.wait_with_output().expect("Failed to wait for server to exit");
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("operation=INSERT key=after_torn_slot status=Ok"));
+    assert!(log.contains("skipping request slot abandoned by crashed client"));
+}
@@ -0,0 +1,68 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use proptest::prelude::*;
+use shared_serve::command::{parse_line, quote, split_commands, tokenize, TextRequest};
+use shared_serve::Operation;
+use std::fmt::Write;
+
+/// Text the server can store: any UTF-8 without NUL bytes.
+fn storable() -> impl Strategy<Value = String> {
+    any::<String>().prop_map(|text| text.replace('\0', ""))
+}
+
+/// Lines built from the characters the tokenizer treats specially, so
+/// quotes, escapes and literals meet each other far more often than in random text.
+fn protocol_line() -> impl Strategy<Value = String> {
+    let pieces = prop::sample::select(vec![
+        "\"", "'", ";", "\\", "\\x", "\\n", "\\ ", " ", "\t", "hex:", "base64:", "41", "zz", "QQ==", "=", "GET", "INSERT", "é", "\0",
+    ]);
+    prop::collection::vec(pieces, 0..24).prop_map(|pieces| pieces.concat())
+}
+
+proptest! {
+    #[test]
+    fn test_tokenize_never_panics(line in any::<String>()) {
+        let _ = tokenize(&line);
+        let _ = parse_line(&line);
+    }
+
+    #[test]
+    fn test_tokenize_protocol_characters(line in protocol_line()) {
+        if let Ok(tokens) = tokenize(&line) {
+            // Whatever was accepted reads back the same once quoted
+            let quoted: Vec<String> = tokens.iter().map(|token| quote(token)).collect();
+            prop_assert_eq!(tokenize(&quoted.join(" ")).unwrap(), tokens);
+        }
+    }
+
+    #[test]
+    fn test_quote_round_trip(tokens in prop::collection::vec(storable(), 0..8), separator in "[ \t]{1,3}") {
+        let quoted: Vec<String> = tokens.iter().map(|token| quote(token)).collect();
+        prop_assert_eq!(tokenize(&quoted.join(&separator)).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_split_commands_round_trip(commands in prop::collection::vec(prop::collection::vec(storable(), 1..4), 0..4)) {
+        let line: Vec<String> = commands
+            .iter()
+            .map(|tokens| tokens.iter().map(|token| quote(token)).collect::<Vec<_>>().join(" "))
+            .collect();
+        prop_assert_eq!(split_commands(&line.join(";")).unwrap(), commands);
+    }
+
+    #[test]
+    fn test_binary_literals_round_trip(text in storable()) {
+        let hex = text.bytes().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        });
+        prop_assert_eq!(tokenize(&format!("hex:{}", hex)).unwrap(), vec![text.clone()]);
+        prop_assert_eq!(tokenize(&format!("base64:{}", BASE64.encode(&text))).unwrap(), vec![text]);
+    }
+
+    #[test]
+    fn test_request_display_round_trip(key in storable(), value in storable()) {
+        let request = TextRequest { operation: Operation::INSERT, key, value };
+        prop_assert_eq!(parse_line(&request.to_string()).unwrap(), vec![request]);
+    }
+}
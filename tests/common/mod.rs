@@ -6,27 +6,76 @@ use std::io::BufReader;
 use std::io::BufRead;
 use std::time::Instant;
 use std::time::Duration;
-use std::process::ChildStdout;
+use std::io::Read;
 
 pub const BUCKET_COUNT: usize = 10;
 
 pub fn start_server() -> Child {
     Command::new("cargo")
-        .args(&["run", "--bin", "server", "--", "--size", &BUCKET_COUNT.to_string()])
-        .stdout(Stdio::piped())
+        .args(["run", "--bin", "server", "--", "--size", &BUCKET_COUNT.to_string(), "--log-level", "debug"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server")
+}
+
+/// Starts a server on its own segment so the test can run alongside others.
+/// The log, including a line per request, is piped to the test.
+pub fn start_server_named(name: &str) -> Child {
+    Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--size", &BUCKET_COUNT.to_string(), "--name", name])
+        .args(["--log-level", "debug"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server")
+}
+
+/// Like `start_server_named`, but only logs warnings for tests that send many requests.
+pub fn start_quiet_server_named(name: &str) -> Child {
+    start_quiet_server_named_with(name, &[])
+}
+
+pub fn start_quiet_server_named_with(name: &str, extra_args: &[&str]) -> Child {
+    Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--size", &BUCKET_COUNT.to_string(), "--name", name])
+        .args(["--log-level", "warn"])
+        .args(extra_args)
         .spawn()
         .expect("Failed to start server")
 }
 
 pub fn start_client() -> Child {
     Command::new("cargo")
-        .args(&["run", "--bin", "client", "--", "--stress-test"])
+        .args(["run", "--bin", "client", "--", "batch"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .expect("Failed to start client")
 }
 
+/// Whether a running server owns the segment, rather than one left behind by a crashed server.
+fn server_running(name: &str) -> bool {
+    shared_serve::Segment::open(name).is_ok_and(|segment| {
+        let pid = segment.header().server_pid.load(std::sync::atomic::Ordering::Acquire);
+        shared_serve::segment::process_alive(pid)
+    })
+}
+
+/// Connects to the server as soon as it has set up its segment.
+pub fn connect_when_ready(name: &str, timeout: Duration) -> shared_serve::Client {
+    let start_time = Instant::now();
+    loop {
+        if server_running(name) {
+            if let Ok(client) = shared_serve::Client::connect(name) {
+                return client;
+            }
+        }
+        if start_time.elapsed() > timeout {
+            panic!("Server did not come up");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
 pub fn stop_server_with_sigint(server: &Child) {
     // Send SIGINT to the server
     signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGINT)
@@ -34,7 +83,8 @@ pub fn stop_server_with_sigint(server: &Child) {
 }
 
 
-pub fn check_server_output(server_output: ChildStdout, expected_output: Vec<&str>, timeout: Duration)  {
+/// Reads the server's log until every expected fragment appeared in some line.
+pub fn check_server_output<R: Read>(server_output: R, expected_output: Vec<&str>, timeout: Duration)  {
     let mut bufread = BufReader::new(server_output);
     let mut buf = String::new();
     let mut found_lines = vec![false; expected_output.len()];
@@ -0,0 +1,118 @@
+use shared_serve::{ClientError, Segment};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+mod common;
+
+fn write_config(test: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("shared_serve_{}_{}.toml", test, std::process::id()));
+    std::fs::write(&path, contents).expect("Failed to write config file");
+    path
+}
+
+#[test]
+fn test_server_reads_config_file() {
+    const SEGMENT_NAME: &str = "ConfigFileTestQueue";
+    let path = write_config(
+        "config",
+        &format!(
+            r#"
+            [segment]
+            name = "{}"
+            capacity = 32
+            mode = 0o640
+
+            [table]
+            size = 4
+            max_keys = 2
+            eviction = "reject"
+
+            [workers]
+            threads = 1
+
+            [persistence]
+            preserve_queue = true
+
+            [logging]
+            level = "debug"
+            "#,
+            SEGMENT_NAME
+        ),
+    );
+    let mut server = Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--config", path.to_str().unwrap()])
+        // Flags take precedence over the environment, which takes precedence over the file
+        .args(["--log-level", "warn", "--preserve-queue=false"])
+        .env("SHARED_SERVE_WORKERS_THREADS", "2")
+        .env("SHARED_SERVE_LOGGING_LEVEL", "trace")
+        .spawn()
+        .expect("Failed to start server");
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    client.insert("key1", "value1").unwrap();
+    client.insert("key2", "value2").unwrap();
+    assert!(matches!(client.insert("key3", "value3"), Err(ClientError::TableFull)));
+    // Existing keys can still be updated
+    client.insert("key1", "value3").unwrap();
+    assert_eq!(client.get("key1").unwrap().as_deref(), Some("value3"));
+    assert_eq!(client.get("key3").unwrap(), None);
+    assert_eq!(client.stats().unwrap().workers, 2);
+    // Clients take the size of the ring from the segment
+    assert_eq!(Segment::open(SEGMENT_NAME).unwrap().capacity(), 32);
+
+    let mode = std::fs::metadata(format!("/dev/shm/{}", SEGMENT_NAME)).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    // A second server refused on the segment leaves its permissions alone
+    let status = common::start_quiet_server_named(SEGMENT_NAME).wait().expect("Failed to wait for second server");
+    assert!(!status.success(), "Second server started on a segment in use");
+    let mode = std::fs::metadata(format!("/dev/shm/{}", SEGMENT_NAME)).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    // --preserve-queue=false turned off the preserve_queue of the file
+    assert!(!std::path::Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_invalid_config_reports_every_error() {
+    let path = write_config(
+        "invalid_config",
+        r#"
+        [segment]
+        mode = 0o1777
+
+        [table]
+        size = 0
+
+        [workers]
+        threads = "many"
+        "#,
+    );
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--config", path.to_str().unwrap()])
+        .args(["--metrics-interval", "0"])
+        .env("SHARED_SERVE_TABLE_EVICTION", "random")
+        .env("SHARED_SERVE_TABLE_COLOR", "red")
+        .stderr(Stdio::piped())
+        .output()
+        .expect("Failed to run server");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(2), "Server did not reject the config: {}", stderr);
+    assert!(stderr.contains("Invalid configuration:"), "{}", stderr);
+    assert!(stderr.contains("[workers]"), "{}", stderr);
+    assert!(stderr.contains("SHARED_SERVE_TABLE_EVICTION: invalid value `random`"), "{}", stderr);
+    // A variable that names no setting is no error
+    assert!(!stderr.contains("SHARED_SERVE_TABLE_COLOR"), "{}", stderr);
+    assert!(stderr.contains("segment.mode 0o1777"), "{}", stderr);
+    assert!(stderr.contains("table.size must be greater than 0"), "{}", stderr);
+    assert!(stderr.contains("metrics.interval must be greater than 0"), "{}", stderr);
+    // No server came up, so no segment was created
+    assert!(!stderr.contains("server started"), "{}", stderr);
+    let _ = std::fs::remove_file(&path);
+}
@@ -23,11 +23,17 @@ fn test_end_to_end_request_handling() {
 
     thread::sleep(Duration::from_secs(2));
     
-    let expected_output = vec!["Inserting key: test_key", "Getting key: test_key", "Value: test_value", "Deleting key: test_key"];
-    common::check_server_output(server.stdout.take().unwrap(), expected_output, Duration::from_secs(60));
+    let expected_output = vec![
+        "processed request client=",
+        "operation=INSERT key=test_key status=Ok",
+        "operation=GET key=test_key status=Ok",
+        "operation=DELETE key=test_key status=Ok",
+    ];
+    common::check_server_output(server.stderr.take().unwrap(), expected_output, Duration::from_secs(60));
 
     // Cleanup
     common::stop_server_with_sigint(&server);
     server.wait().expect("Failed to wait for server to exit");
-    client.wait().expect("Failed to kill client");
+    let client_output = client.wait_with_output().expect("Failed to kill client");
+    assert!(String::from_utf8_lossy(&client_output.stdout).contains("Value: test_value"));
 } 
\ No newline at end of file
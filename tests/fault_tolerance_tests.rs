@@ -13,6 +13,7 @@ fn test_fault_tolerance_on_client_crash() {
 
     // Simulate client crash
     client.kill().expect("Failed to kill client abruptly");
+    client.wait().expect("Failed to reap killed client");
 
     thread::sleep(Duration::from_secs(2));
 
@@ -28,10 +29,10 @@ fn test_fault_tolerance_on_client_crash() {
 
     thread::sleep(Duration::from_secs(2));
 
-    // Store server's stdout before sending SIGINT
-    let server_output = server.stdout.take().unwrap();
+    // Store server's log before sending SIGINT
+    let server_output = server.stderr.take().unwrap();
 
-    common::check_server_output(server_output, vec!["Inserting key: new_key"], Duration::from_secs(10));
+    common::check_server_output(server_output, vec!["operation=INSERT key=new_key"], Duration::from_secs(10));
 
     common::stop_server_with_sigint(&server);
 
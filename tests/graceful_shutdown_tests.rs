@@ -1,6 +1,12 @@
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use shared_serve::{Client, ClientConfig, ClientError, Reservation, RetryPolicy, Segment};
+use shared_serve::segment::STATE_SHUTTING_DOWN;
 
 mod common;
 
@@ -25,4 +31,124 @@ fn test_graceful_shutdown() {
 
     println!("Killing client");
     client.kill().expect("Failed to kill client");
-} 
\ No newline at end of file
+    client.wait().expect("Failed to reap client");
+} 
+
+fn wait_for_exit(server: &mut std::process::Child, timeout: Duration) -> bool {
+    let start_time = Instant::now();
+    while start_time.elapsed() < timeout {
+        if server.try_wait().expect("Failed to wait for server").is_some() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn wait_for_shutdown_state(name: &str) {
+    let segment = Segment::open(name).expect("Failed to open segment");
+    let start_time = Instant::now();
+    while segment.header().state.load(Ordering::Acquire) != STATE_SHUTTING_DOWN {
+        assert!(start_time.elapsed() < Duration::from_secs(10), "Server did not start shutting down");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_sigterm_drains_pending_requests() {
+    const SEGMENT_NAME: &str = "DrainTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    // Queue up requests while the server can't process them
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGSTOP).unwrap();
+    // Without reconnecting, the clients stay on the segment of this server
+    let config = ClientConfig { reconnect: RetryPolicy::never(), ..ClientConfig::default() };
+    let clients: Vec<Arc<Client>> = (0..5)
+        .map(|_| Arc::new(Client::connect_with(SEGMENT_NAME, config.clone()).unwrap()))
+        .collect();
+    let handles: Vec<_> = clients
+        .iter()
+        .enumerate()
+        .map(|(i, client)| {
+            let client = client.clone();
+            thread::spawn(move || client.insert(&format!("key{}", i), "value"))
+        })
+        .collect();
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    while segment.header().pending() < clients.len() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGTERM).unwrap();
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGCONT).unwrap();
+    for handle in handles {
+        handle.join().unwrap().expect("Pending request was dropped on shutdown");
+    }
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)), "Server did not exit after draining");
+    assert!(!Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists(), "Shared memory was not cleaned up");
+
+    // The old mapping stays readable, but no longer accepts requests
+    assert!(matches!(clients[0].insert("late", "value"), Err(ClientError::ShuttingDown)));
+}
+
+#[test]
+fn test_second_sigint_skips_drain() {
+    const SEGMENT_NAME: &str = "SecondInterruptTestQueue";
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--drain-timeout", "60"]);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    // A reserved but never committed slot keeps the drain waiting
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    assert!(matches!(segment.try_reserve(), Reservation::Reserved(_)));
+
+    common::stop_server_with_sigint(&server);
+    wait_for_shutdown_state(SEGMENT_NAME);
+    assert!(!wait_for_exit(&mut server, Duration::from_millis(500)), "Server exited with a request pending");
+
+    common::stop_server_with_sigint(&server);
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)), "Server did not exit on the second SIGINT");
+    assert!(!Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists(), "Shared memory was not cleaned up");
+}
+
+#[test]
+fn test_drain_timeout() {
+    const SEGMENT_NAME: &str = "DrainTimeoutTestQueue";
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--drain-timeout", "1"]);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    assert!(matches!(segment.try_reserve(), Reservation::Reserved(_)));
+
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGHUP).unwrap();
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)), "Server did not give up draining");
+    assert!(!Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists(), "Shared memory was not cleaned up");
+}
+
+#[test]
+fn test_shutdown_with_write_lock_held() {
+    // A client stopped in the middle of a reservation keeps the write lock
+    let hold_write_lock = |name: &str| {
+        let segment = Segment::open(name).unwrap();
+        segment.header().write_lock.store(std::process::id(), Ordering::SeqCst);
+        segment
+    };
+
+    const SEGMENT_NAME: &str = "WriteLockInterruptTestQueue";
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--drain-timeout", "60"]);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let _segment = hold_write_lock(SEGMENT_NAME);
+    common::stop_server_with_sigint(&server);
+    wait_for_shutdown_state(SEGMENT_NAME);
+    assert!(!wait_for_exit(&mut server, Duration::from_millis(500)), "Server exited while a client reserved a slot");
+    common::stop_server_with_sigint(&server);
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)), "Server did not exit on the second SIGINT");
+
+    const TIMEOUT_SEGMENT_NAME: &str = "WriteLockTimeoutTestQueue";
+    let mut server = common::start_quiet_server_named_with(TIMEOUT_SEGMENT_NAME, &["--drain-timeout", "1"]);
+    drop(common::connect_when_ready(TIMEOUT_SEGMENT_NAME, Duration::from_secs(60)));
+    let _segment = hold_write_lock(TIMEOUT_SEGMENT_NAME);
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGHUP).unwrap();
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)), "Server did not give up waiting for the write lock");
+    assert!(!Path::new(&format!("/dev/shm/{}", TIMEOUT_SEGMENT_NAME)).exists(), "Shared memory was not cleaned up");
+}
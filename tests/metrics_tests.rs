@@ -0,0 +1,67 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+mod common;
+
+fn scrape(port: u16, path: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("Failed to connect to metrics endpoint");
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_metrics_endpoint() {
+    const SEGMENT_NAME: &str = "MetricsEndpointTestQueue";
+    // Let the OS pick a free port for the server to use
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--metrics-port", &port.to_string()]);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    client.insert("key", "value").unwrap();
+    client.get("key").unwrap();
+    client.get("missing").unwrap();
+
+    let response = scrape(port, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Unexpected response: {}", response);
+    assert!(response.contains("shared_serve_requests_total{operation=\"get\"} 2\n"));
+    assert!(response.contains("shared_serve_get_hits_total 1\n"));
+    assert!(response.contains("shared_serve_get_misses_total 1\n"));
+    assert!(response.contains("shared_serve_keys 1\n"));
+    assert!(response.contains("shared_serve_request_duration_seconds_count{operation=\"insert\"} 1\n"));
+    assert!(response.contains("shared_serve_request_duration_seconds_bucket{operation=\"get\",le=\"+Inf\"} 2\n"));
+    assert!(scrape(port, "/").starts_with("HTTP/1.1 404"));
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_metrics_textfile() {
+    const SEGMENT_NAME: &str = "MetricsTextfileTestQueue";
+    let path = std::env::temp_dir().join(format!("shared_serve_metrics_{}.prom", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut server = common::start_quiet_server_named_with(
+        SEGMENT_NAME,
+        &["--metrics-file", path.to_str().unwrap(), "--metrics-interval", "1"],
+    );
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    client.insert("key", "value").unwrap();
+
+    // Wait for a write that happened after the insert
+    let start_time = Instant::now();
+    loop {
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+        if contents.contains("shared_serve_requests_total{operation=\"insert\"} 1\n") {
+            break;
+        }
+        assert!(start_time.elapsed() < Duration::from_secs(10), "Metrics file was not updated: {}", contents);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    let _ = std::fs::remove_file(&path);
+}
@@ -30,15 +30,13 @@ fn test_queue_full_handling() {
     }
 
     let mut queue_full_count = 0;
-    let mut client_id = 0;
-    for client in clients {
+    for (client_id, client) in clients.into_iter().enumerate() {
         println!("Client {}:", client_id);
         let output = client.wait_with_output().expect("Failed to get client output");
         if String::from_utf8_lossy(&output.stdout).contains("Queue is full") {
             queue_full_count += 1;
             println!("Client {} reported queue full", client_id);
         }
-        client_id += 1;
     }
 
     assert!(queue_full_count > 0, "No clients reported queue full errors");
@@ -49,7 +47,7 @@ fn test_queue_full_handling() {
     thread::sleep(Duration::from_secs(1));
 
     // Check that the server starts processing requests after resuming
-    common::check_server_output(server.stdout.take().unwrap(), vec!["Server: Received request at position"], Duration::from_secs(10));
+    common::check_server_output(server.stderr.take().unwrap(), vec!["dequeued request"], Duration::from_secs(10));
 
     common::stop_server_with_sigint(&server);
     server.wait().expect("Failed to wait for server to exit");
@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::time::Duration;
+use shared_serve::{Client, ClientConfig, ClientError};
+mod common;
+
+const SEGMENT_NAME: &str = "QueuePersistenceTestQueue";
+
+#[test]
+fn test_pending_requests_survive_restart() {
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--preserve-queue"]);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    // Move the read index away from the start of the ring
+    for i in 0..7 {
+        client.insert(&format!("key{}", i), "value").unwrap();
+    }
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists(), "Segment was removed on shutdown");
+
+    // Requests enqueued while no server is running wait in the ring
+    let config = ClientConfig { reply_timeout: Duration::from_millis(200), ..ClientConfig::default() };
+    let impatient_client = Client::connect_with(SEGMENT_NAME, config).expect("Failed to connect to preserved segment");
+    for i in 0..5 {
+        let result = impatient_client.insert(&format!("pending{}", i), &format!("value{}", i));
+        assert!(matches!(result, Err(ClientError::ReplyTimeout)));
+    }
+
+    // The next server resumes draining the ring where the previous one stopped
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    for i in 0..5 {
+        assert_eq!(client.get(&format!("pending{}", i)).unwrap(), Some(format!("value{}", i)));
+    }
+    drop(client);
+    drop(impatient_client);
+
+    // Without --preserve-queue the segment is removed as usual
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(!Path::new(&format!("/dev/shm/{}", SEGMENT_NAME)).exists(), "Segment was not cleaned up");
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use shared_serve::{AsyncClient, Client, ClientConfig, ClientError, RetryPolicy};
+mod common;
+
+#[test]
+fn test_client_reattaches_after_restart() {
+    const SEGMENT_NAME: &str = "ReconnectRestartTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    let async_client = AsyncClient::connect(SEGMENT_NAME).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    client.insert("before", "restart").unwrap();
+    runtime.block_on(async_client.insert("async", "before")).unwrap();
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    // The new server starts out empty, but serves the old clients
+    assert_eq!(client.get("before").unwrap(), None);
+    client.insert("after", "restart").unwrap();
+    assert_eq!(client.get("after").unwrap(), Some("restart".to_string()));
+    runtime.block_on(async_client.insert("async", "after")).unwrap();
+    assert_eq!(runtime.block_on(async_client.get("async")).unwrap(), Some("after".to_string()));
+
+    drop(client);
+    drop(async_client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_client_reattaches_after_crash() {
+    const SEGMENT_NAME: &str = "ReconnectCrashTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    client.insert("before", "crash").unwrap();
+
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGKILL).unwrap();
+    server.wait().expect("Failed to wait for server to exit");
+    // The new server resets the segment left behind, including the client registrations
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    client.insert("after", "crash").unwrap();
+    assert_eq!(client.get("after").unwrap(), Some("crash".to_string()));
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_reconnect_gives_up_after_retry_budget() {
+    const SEGMENT_NAME: &str = "ReconnectBudgetTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let reconnect = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(10),
+    };
+    let config = ClientConfig { reconnect, ..ClientConfig::default() };
+    let client = Client::connect_with(SEGMENT_NAME, config).unwrap();
+    client.insert("key", "value").unwrap();
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(matches!(client.insert("key", "value"), Err(ClientError::Disconnected)));
+}
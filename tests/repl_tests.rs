@@ -0,0 +1,74 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+mod common;
+
+/// Types `lines` into the REPL of a client and returns what it printed.
+fn repl(name: &str, args: &[&str], lines: &[&str]) -> String {
+    let mut client = Command::new("cargo")
+        .args(["run", "--bin", "client", "--", "--name", name])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start client");
+    let mut stdin = client.stdin.take().unwrap();
+    for line in lines {
+        writeln!(stdin, "{}", line).unwrap();
+    }
+    drop(stdin);
+    let output = client.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_repl_commands_and_history() {
+    const SEGMENT_NAME: &str = "ReplTestQueue";
+    let history = std::env::temp_dir().join(format!("repl_test_history_{}", std::process::id()));
+    let _ = fs::remove_file(&history);
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    let history_arg = history.to_str().unwrap();
+    let output = repl(
+        SEGMENT_NAME,
+        &["interactive", "--history", history_arg],
+        &["INSERT greeting \"hello world\"; GET greeting", "", "GET missing", "help", "GET greeting; SET x", "exit", "GET never"],
+    );
+    assert!(output.contains("OK ("), "{}", output);
+    assert!(output.contains("\"hello world\" ("), "{}", output);
+    assert!(output.contains("(not found) ("), "{}", output);
+    assert!(output.contains("INSERT <key> <value>"), "{}", output);
+    assert!(output.contains("SCAN [<cursor>]"), "{}", output);
+    // The line with the unknown command ran none of its commands
+    assert_eq!(output.matches("\"hello world\" (").count(), 1, "{}", output);
+    assert!(output.contains("Invalid operation: SET"), "{}", output);
+
+    let saved = fs::read_to_string(&history).unwrap();
+    assert!(saved.contains("INSERT greeting \"hello world\"; GET greeting"), "{}", saved);
+    assert!(saved.contains("GET missing"), "{}", saved);
+    assert!(!saved.contains("GET never"), "{}", saved);
+
+    // Each command is answered with one JSON object including its latency
+    let output = repl(SEGMENT_NAME, &["--output", "json", "interactive", "--no-history"], &["GET greeting"]);
+    let reply: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(reply["value"], "hello world");
+    assert!(reply["latency_ms"].as_f64().unwrap() > 0.0);
+
+    // KEYS lists every key, SCAN one page of them with the cursor of the next
+    let output = repl(SEGMENT_NAME, &["--output", "json", "interactive", "--no-history"], &["KEYS; SCAN", "SCAN 0:x"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", output);
+    let keys: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(keys["keys"], serde_json::json!(["greeting"]));
+    let page: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(page["keys"], serde_json::json!(["greeting"]));
+    assert!(page["next"].is_null());
+    assert!(lines[2].contains("SCAN takes a cursor"), "{}", output);
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    fs::remove_file(&history).unwrap();
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+use shared_serve::{Client, ClientConfig, ClientError, RetryPolicy};
+mod common;
+
+const SEGMENT_NAME: &str = "ServerCrashTestQueue";
+
+/// A client that keeps enqueueing into the segment of a crashed server instead of waiting for the next one.
+fn impatient_client() -> Client {
+    let config = ClientConfig {
+        reply_timeout: Duration::from_millis(200),
+        reconnect: RetryPolicy::never(),
+        ..ClientConfig::default()
+    };
+    Client::connect_with(SEGMENT_NAME, config).expect("Failed to connect to segment")
+}
+
+/// Reads a key once the restarted server has come up and answers again.
+fn get_after_restart(client: &Client, key: &str) -> Option<String> {
+    for _ in 0..60 {
+        match client.get(key) {
+            Ok(value) => return value,
+            Err(ClientError::ReplyTimeout) => continue,
+            Err(e) => panic!("Get failed: {}", e),
+        }
+    }
+    panic!("Restarted server never answered");
+}
+
+#[test]
+fn test_server_crash_recovery() {
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    // A second server must not take over the segment of a running one
+    let status = common::start_quiet_server_named(SEGMENT_NAME).wait().expect("Failed to wait for second server");
+    assert!(!status.success(), "Second server started on a segment in use");
+    client.insert("key", "value").expect("First server stopped serving");
+
+    // Crash the server, a request enqueued meanwhile stays in the ring
+    server.kill().expect("Failed to kill server");
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(matches!(impatient_client().insert("pending_key", "pending_value"), Err(ClientError::ReplyTimeout)));
+
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--recover"]);
+    assert_eq!(get_after_restart(&client, "pending_key"), Some("pending_value".to_string()));
+
+    // Without --recover the stale segment is reset and the pending request dropped
+    server.kill().expect("Failed to kill server");
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(matches!(impatient_client().insert("dropped_key", "dropped_value"), Err(ClientError::ReplyTimeout)));
+
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    assert_eq!(get_after_restart(&client, "dropped_key"), None);
+    drop(client);
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
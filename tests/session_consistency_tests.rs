@@ -0,0 +1,123 @@
+use std::thread;
+use std::time::Duration;
+use shared_serve::{AsyncClient, Client, ClientError, ClientId, Operation, Request, Reservation, Segment, NO_REPLY};
+mod common;
+
+const SEGMENT_NAME: &str = "SessionConsistencyTestQueue";
+const ROUNDS: u64 = 300;
+
+fn parse(value: Option<String>) -> u64 {
+    value.map(|v| v.parse().unwrap()).unwrap_or(0)
+}
+
+/// Reads a key, retrying while the writer keeps the queue full.
+fn read(client: &Client, key: &str) -> u64 {
+    loop {
+        match client.get(key) {
+            Ok(value) => return parse(value),
+            Err(ClientError::QueueFull) => thread::yield_now(),
+            Err(e) => panic!("Get failed: {}", e),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_client_session_fifo() {
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let observer = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+    let writer = AsyncClient::connect(SEGMENT_NAME).expect("Failed to connect to server");
+
+    // With 10 buckets and 4 workers, "a", "k" and "u" share a worker while "b" lives on another.
+    // The writer always updates "a" before "b", so anyone who sees "b" at round i must see
+    // "a" at round i or later, even when the worker of "a" is busy with "k" and "u".
+    let observer = thread::spawn(move || {
+        loop {
+            let b = read(&observer, "b");
+            let a = read(&observer, "a");
+            assert!(a >= b, "Saw b={} before a={}", b, a);
+            if b == ROUNDS {
+                break;
+            }
+        }
+    });
+
+    // Pipeline all requests without waiting for replies in between
+    let mut writes = Vec::new();
+    let mut reads = Vec::new();
+    for i in 1..=ROUNDS {
+        writes.push(writer.insert("k", &i.to_string()));
+        writes.push(writer.insert("u", &i.to_string()));
+        writes.push(writer.insert("a", &i.to_string()));
+        writes.push(writer.insert("b", &i.to_string()));
+        writes.push(writer.insert(&format!("key{}", i), &i.to_string()));
+        reads.push((i, writer.get(&format!("key{}", i))));
+    }
+    for write in writes {
+        write.await.expect("Insert failed");
+    }
+    // Every read observes the write submitted just before it
+    for (i, read) in reads {
+        assert_eq!(parse(read.await.expect("Get failed")), i);
+    }
+
+    observer.join().expect("Observer saw writes out of order");
+    drop(writer);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+/// Writes the requests into the ring of the segment one after another, as if sent by the clients stamped on them.
+fn enqueue(segment: &Segment, requests: &[Request]) {
+    for request in requests {
+        loop {
+            match segment.try_reserve() {
+                Reservation::Reserved(slot) => break slot.commit(request),
+                Reservation::Full | Reservation::Locked => thread::yield_now(),
+                Reservation::Closed => panic!("Server is shutting down"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_clients_on_one_key_keep_dequeue_order() {
+    const SEGMENT_NAME: &str = "SessionKeyOrderTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    drop(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    let writer = ClientId { pid: std::process::id(), slot: 5000 };
+    let reader = ClientId { pid: std::process::id(), slot: 5001 };
+    let reply_slot = segment.reply_slot(0).unwrap();
+
+    // "a", "k" and "u" share a worker, "b" lives on another. The writer's update of "b" waits
+    // for its updates on the busy worker, and the reader's later read of "b" must wait for it.
+    for round in 1..=200u64 {
+        let mut requests = Vec::new();
+        for (i, key) in ["a", "k", "u"].iter().cycle().take(7).enumerate() {
+            let mut request = Request::new(Operation::INSERT, key, &round.to_string());
+            request.reply_slot = NO_REPLY;
+            request.stamp(writer, round * 10 + i as u64);
+            requests.push(request);
+        }
+        let mut write = Request::new(Operation::INSERT, "b", &round.to_string());
+        write.reply_slot = NO_REPLY;
+        write.stamp(writer, round * 10 + 8);
+        let mut read = Request::new(Operation::GET, "b", "");
+        assert!(reply_slot.try_claim());
+        read.reply_slot = 0;
+        read.stamp(reader, round);
+        requests.extend([write, read]);
+        enqueue(&segment, &requests);
+
+        let reply = loop {
+            match reply_slot.take() {
+                Some(reply) => break reply,
+                None => thread::yield_now(),
+            }
+        };
+        assert_eq!(reply.value, round.to_string(), "Read overtook the earlier write in round {}", round);
+    }
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
@@ -0,0 +1,116 @@
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use shared_serve::Segment;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+mod common;
+
+fn shmadmin(args: &[&str]) -> String {
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "shmadmin", "--"])
+        .args(args)
+        .output()
+        .expect("Failed to run shmadmin");
+    assert!(output.status.success(), "shmadmin failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_inspect_pending_requests() {
+    const SEGMENT_NAME: &str = "ShmadminTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = Arc::new(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    // Keep the requests in the ring while they are inspected
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGSTOP).unwrap();
+    let handles: Vec<_> = (0..2)
+        .map(|i| {
+            let client = client.clone();
+            thread::spawn(move || client.insert(&format!("key{}", i), "value"))
+        })
+        .collect();
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    while segment.header().pending() < 2 {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let text = shmadmin(&["inspect", "--name", SEGMENT_NAME]);
+    assert!(text.contains("Pending: 2"), "{}", text);
+    assert!(text.contains(&format!("server pid {} (alive)", server.id())), "{}", text);
+    assert!(text.contains("Write lock: unlocked"), "{}", text);
+    assert!(text.contains(&format!("Client: {}, Request ID: ", client.id())), "{}", text);
+    assert!(text.contains("Operation: INSERT, Key: key"), "{}", text);
+
+    let json: serde_json::Value = serde_json::from_str(&shmadmin(&["inspect", "--name", SEGMENT_NAME, "--json"])).unwrap();
+    assert_eq!(json["valid"], true);
+    assert_eq!(json["header"]["pending"], 2);
+    let slots = json["slots"].as_array().unwrap();
+    assert_eq!(slots.len(), 2);
+    assert_eq!(slots[0]["state"], "committed");
+    assert_eq!(slots[0]["request"]["operation"], "INSERT");
+    assert_eq!(slots[0]["request"]["client"], client.id().to_string());
+
+    let all: serde_json::Value = serde_json::from_str(&shmadmin(&["inspect", "--name", SEGMENT_NAME, "--json", "--all"])).unwrap();
+    assert_eq!(all["slots"].as_array().unwrap().len(), shared_serve::DEFAULT_CAPACITY);
+
+    // Inspecting left the requests for the server
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGCONT).unwrap();
+    for handle in handles {
+        handle.join().unwrap().expect("Inspected request was not processed");
+    }
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+fn start_and_wait(name: &str, extra_args: &[&str]) -> std::process::Child {
+    let server = common::start_quiet_server_named_with(name, extra_args);
+    drop(common::connect_when_ready(name, Duration::from_secs(60)));
+    server
+}
+
+fn segment_exists(name: &str) -> bool {
+    Path::new(&format!("/dev/shm/{}", name)).exists()
+}
+
+#[test]
+fn test_cleanup_removes_orphaned_segments() {
+    const ORPHANED: &str = "CleanupOrphanedTestQueue";
+    const LIVE: &str = "CleanupLiveTestQueue";
+    const PRESERVED: &str = "CleanupPreservedTestQueue";
+
+    let mut crashed = start_and_wait(ORPHANED, &[]);
+    crashed.kill().expect("Failed to kill server");
+    crashed.wait().expect("Failed to wait for server to exit");
+    let mut preserving = start_and_wait(PRESERVED, &["--preserve-queue"]);
+    common::stop_server_with_sigint(&preserving);
+    preserving.wait().expect("Failed to wait for server to exit");
+    let mut live = start_and_wait(LIVE, &[]);
+
+    let report = shmadmin(&["cleanup", "--dry-run", ORPHANED, LIVE, PRESERVED]);
+    assert!(
+        report.contains(&format!("{}: orphaned, server pid {} (dead), 0 pending, would remove", ORPHANED, crashed.id())),
+        "{}",
+        report
+    );
+    assert!(report.contains(&format!("{}: live, server pid {} (alive), 0 pending, kept", LIVE, live.id())), "{}", report);
+    assert!(report.contains(&format!("{}: preserved", PRESERVED)), "{}", report);
+    assert!(segment_exists(ORPHANED), "Dry run removed a segment");
+
+    let report = shmadmin(&["cleanup", ORPHANED, LIVE, PRESERVED]);
+    assert!(report.contains(&format!("{}: orphaned", ORPHANED)) && report.contains("removed"), "{}", report);
+    assert!(!segment_exists(ORPHANED));
+    assert!(segment_exists(LIVE));
+    assert!(segment_exists(PRESERVED));
+
+    let report = shmadmin(&["cleanup", "--force", PRESERVED]);
+    assert!(report.contains(&format!("{}: preserved", PRESERVED)) && report.ends_with("removed\n"), "{}", report);
+    assert!(!segment_exists(PRESERVED));
+
+    common::stop_server_with_sigint(&live);
+    live.wait().expect("Failed to wait for server to exit");
+}
@@ -0,0 +1,33 @@
+use shared_serve::DEFAULT_SEGMENT_NAME;
+use std::process::Command;
+use std::time::Duration;
+mod common;
+
+/// Runs one client command and returns its exit status and stdout.
+fn client(args: &[&str]) -> (i32, String) {
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "client", "--"])
+        .args(args)
+        .output()
+        .expect("Failed to run client");
+    (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn test_single_command_exit_codes() {
+    let mut server = common::start_quiet_server_named(DEFAULT_SEGMENT_NAME);
+    drop(common::connect_when_ready(DEFAULT_SEGMENT_NAME, Duration::from_secs(60)));
+
+    assert_eq!(client(&["get", "script_key"]), (1, String::new()));
+    assert_eq!(client(&["insert", "script_key", "script_value"]), (0, "OK\n".to_string()));
+    assert_eq!(client(&["get", "script_key"]), (0, "script_value\n".to_string()));
+    assert_eq!(client(&["delete", "script_key"]), (0, "OK\n".to_string()));
+    assert_eq!(client(&["delete", "script_key"]), (1, String::new()));
+    assert_eq!(client(&["insert", "script_key"]), (2, String::new()));
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+
+    // Nothing to connect to
+    assert_eq!(client(&["get", "script_key"]).0, 2);
+}
@@ -0,0 +1,102 @@
+use shared_serve::wire;
+use shared_serve::{Client, ClientConfig, ClientError, RetryPolicy};
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+mod common;
+
+fn socket_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("shared_serve_{}_{}.sock", test, std::process::id()))
+}
+
+fn connect_socket_when_ready(path: &Path, timeout: Duration) -> Client {
+    let start_time = Instant::now();
+    loop {
+        if let Ok(client) = Client::connect_socket(path) {
+            return client;
+        }
+        if start_time.elapsed() > timeout {
+            panic!("Server did not come up");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_socket_and_segment_share_table() {
+    const SEGMENT_NAME: &str = "SocketTestQueue";
+    let path = socket_path("shared");
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--socket", path.to_str().unwrap()]);
+    let socket_client = connect_socket_when_ready(&path, Duration::from_secs(60));
+    let segment_client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    socket_client.insert("socket_key", "from socket").unwrap();
+    segment_client.insert("segment_key", "from segment").unwrap();
+    assert_eq!(segment_client.get("socket_key").unwrap(), Some("from socket".to_string()));
+    assert_eq!(socket_client.get("segment_key").unwrap(), Some("from segment".to_string()));
+    assert!(socket_client.delete("segment_key").unwrap());
+    assert!(!socket_client.delete("segment_key").unwrap());
+    assert_eq!(socket_client.get("segment_key").unwrap(), None);
+    assert_eq!(socket_client.keys().unwrap(), vec!["socket_key".to_string()]);
+    assert_eq!(socket_client.stats().unwrap().inserts, 2);
+    // The server names connections past the segment's registration slots
+    assert_eq!(socket_client.id().pid, server.id());
+    assert_ne!(socket_client.id(), segment_client.id());
+
+    // The client binary connects the same way
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "client", "--", "--socket", path.to_str().unwrap(), "get", "socket_key"])
+        .output()
+        .expect("Failed to run client");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "from socket\n");
+
+    // A malformed frame closes only the connection that sent it
+    let mut garbage = UnixStream::connect(&path).unwrap();
+    assert!(wire::read_frame(&mut garbage).unwrap().is_some());
+    garbage.write_all(&[0, 0, 0, 2, 9, 9]).unwrap();
+    assert!(wire::read_frame(&mut garbage).unwrap().is_none());
+    assert_eq!(socket_client.get("socket_key").unwrap(), Some("from socket".to_string()));
+
+    // A second server can't take over the socket of a running one
+    let status = Command::new("cargo")
+        .args(["run", "--bin", "server", "--", "--name", "SocketTestOtherQueue", "--socket", path.to_str().unwrap()])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+
+    let config = ClientConfig { reconnect: RetryPolicy::never(), ..ClientConfig::default() };
+    let impatient_client = Client::connect_socket_with(&path, config.clone()).unwrap();
+    drop(segment_client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    assert!(!path.exists());
+
+    assert!(matches!(impatient_client.get("socket_key"), Err(ClientError::Disconnected)));
+    assert!(matches!(Client::connect_socket_with(&path, config), Err(ClientError::Connect(_))));
+}
+
+#[test]
+fn test_socket_client_reconnects_to_restarted_server() {
+    const SEGMENT_NAME: &str = "SocketReconnectTestQueue";
+    let path = socket_path("reconnect");
+    let socket_arg = ["--socket", path.to_str().unwrap()];
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &socket_arg);
+    let client = connect_socket_when_ready(&path, Duration::from_secs(60));
+    client.insert("key", "value").unwrap();
+    let first_id = client.id();
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &socket_arg);
+    drop(connect_socket_when_ready(&path, Duration::from_secs(60)));
+
+    // The table of the new server is empty
+    assert_eq!(client.get("key").unwrap(), None);
+    assert_ne!(client.id(), first_id);
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
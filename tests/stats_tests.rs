@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use shared_serve::{Client, ClientError, Segment, DEFAULT_CAPACITY};
+mod common;
+
+#[test]
+fn test_stats_counts_requests() {
+    const SEGMENT_NAME: &str = "StatsTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    client.insert("key1", "value1").unwrap();
+    client.insert("key2", "value2").unwrap();
+    client.insert("key1", "v").unwrap();
+    assert_eq!(client.get("key1").unwrap(), Some("v".to_string()));
+    assert_eq!(client.get("missing").unwrap(), None);
+    assert!(client.delete("key2").unwrap());
+
+    let stats = client.stats().unwrap();
+    assert_eq!((stats.gets, stats.inserts, stats.deletes, stats.stats), (2, 3, 1, 1));
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+    assert_eq!(stats.keys, 1);
+    assert_eq!(stats.memory_bytes, "key1v".len() as u64);
+    assert_eq!(stats.chain_lengths.iter().sum::<u64>(), common::BUCKET_COUNT as u64);
+    assert_eq!(stats.workers, 4);
+    // The worker answering the STATS request
+    assert_eq!(stats.busy_workers, 1);
+    assert_eq!(stats.queue_depth, 0);
+
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_stats_counts_queue_full_rejections() {
+    const SEGMENT_NAME: &str = "StatsQueueFullTestQueue";
+    let mut server = common::start_quiet_server_named(SEGMENT_NAME);
+    let client = Arc::new(common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60)));
+
+    // Fill the ring while the server can't drain it
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGSTOP).unwrap();
+    let handles: Vec<_> = (0..DEFAULT_CAPACITY - 1)
+        .map(|i| {
+            let client = client.clone();
+            thread::spawn(move || client.insert(&format!("key{}", i), "value"))
+        })
+        .collect();
+    let segment = Segment::open(SEGMENT_NAME).unwrap();
+    while segment.header().pending() < DEFAULT_CAPACITY - 1 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let rejected = Client::connect(SEGMENT_NAME).unwrap();
+    assert!(matches!(rejected.insert("one_too_many", "value"), Err(ClientError::QueueFull)));
+    signal::kill(Pid::from_raw(server.id() as i32), Signal::SIGCONT).unwrap();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+
+    let stats = client.stats().unwrap();
+    assert_eq!(stats.queue_full, 1);
+    assert_eq!(stats.inserts, DEFAULT_CAPACITY as u64 - 1);
+
+    drop(rejected);
+    drop(client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
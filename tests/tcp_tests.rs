@@ -0,0 +1,110 @@
+use shared_serve::wire;
+use shared_serve::{Client, ClientConfig, ClientError, Operation, ReplyStatus, Request, RetryPolicy};
+use std::net::{TcpListener, TcpStream};
+use std::process::Command;
+use std::time::{Duration, Instant};
+mod common;
+
+/// A loopback address with a port that was free a moment ago.
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn connect_tcp_when_ready(address: &str, timeout: Duration) -> Client {
+    let start_time = Instant::now();
+    loop {
+        if let Ok(client) = Client::connect_tcp(address) {
+            return client;
+        }
+        if start_time.elapsed() > timeout {
+            panic!("Server did not come up");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_tcp_pipelining() {
+    const SEGMENT_NAME: &str = "TcpTestQueue";
+    let address = free_address();
+    let mut server = common::start_quiet_server_named_with(SEGMENT_NAME, &["--tcp", &address]);
+    let tcp_client = connect_tcp_when_ready(&address, Duration::from_secs(60));
+    let segment_client = common::connect_when_ready(SEGMENT_NAME, Duration::from_secs(60));
+
+    tcp_client.insert("tcp_key", "over tcp").unwrap();
+    assert_eq!(segment_client.get("tcp_key").unwrap(), Some("over tcp".to_string()));
+    assert!(tcp_client.delete("tcp_key").unwrap());
+    assert_eq!(tcp_client.get("tcp_key").unwrap(), None);
+
+    // Far more requests than are sent ahead at once, each reading the key the previous one wrote
+    let mut requests = Vec::new();
+    for i in 0..300 {
+        requests.push(Request::new(Operation::INSERT, &format!("key{}", i % 7), &i.to_string()));
+        requests.push(Request::new(Operation::GET, &format!("key{}", i % 7), ""));
+    }
+    let replies = tcp_client.pipeline(&requests).unwrap();
+    assert_eq!(replies.len(), requests.len());
+    for (i, pair) in replies.chunks(2).enumerate() {
+        assert_eq!(pair[0].status, ReplyStatus::Ok);
+        assert_eq!((pair[1].status, pair[1].value.as_str()), (ReplyStatus::Ok, i.to_string().as_str()));
+    }
+    assert!(replies.windows(2).all(|pair| pair[0].request_id < pair[1].request_id));
+    assert_eq!(tcp_client.keys().unwrap().len(), 7);
+
+    // The client binary connects the same way
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "client", "--", "--tcp", &address, "get", "key6"])
+        .output()
+        .expect("Failed to run client");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "293\n");
+
+    drop(segment_client);
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}
+
+#[test]
+fn test_tcp_connection_limit_and_idle_timeout() {
+    const SEGMENT_NAME: &str = "TcpLimitTestQueue";
+    let address = free_address();
+    let mut server = common::start_quiet_server_named_with(
+        SEGMENT_NAME,
+        &["--tcp", &address, "--max-connections", "2", "--idle-timeout", "1"],
+    );
+    let first = connect_tcp_when_ready(&address, Duration::from_secs(60));
+    let second = Client::connect_tcp(&address).unwrap();
+    assert!(matches!(Client::connect_tcp(&address), Err(ClientError::TooManyClients)));
+
+    // Closing a connection makes room for another one
+    drop(second);
+    let start_time = Instant::now();
+    let third = loop {
+        match Client::connect_tcp(&address) {
+            Ok(client) => break client,
+            Err(ClientError::TooManyClients) if start_time.elapsed() < Duration::from_secs(10) => {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("{}", e),
+        }
+    };
+    third.insert("key", "value").unwrap();
+
+    // Idle connections are closed, and clients reconnect on their next request
+    std::thread::sleep(Duration::from_millis(1500));
+    let first_id = first.id();
+    assert_eq!(first.get("key").unwrap(), Some("value".to_string()));
+    assert_ne!(first.id(), first_id);
+    let impatient = ClientConfig { reconnect: RetryPolicy::never(), ..ClientConfig::default() };
+    let impatient_client = Client::connect_tcp_with(&address, impatient).unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(matches!(impatient_client.get("key"), Err(ClientError::Disconnected)));
+
+    // The hello is the last frame before the server closes an idle connection
+    let mut raw = TcpStream::connect(&address).unwrap();
+    assert!(wire::read_frame(&mut raw).unwrap().is_some());
+    assert!(wire::read_frame(&mut raw).unwrap().is_none());
+
+    common::stop_server_with_sigint(&server);
+    server.wait().expect("Failed to wait for server to exit");
+}